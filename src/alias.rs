@@ -0,0 +1,114 @@
+//! Aliases of builds
+//!
+//! `llvmenv alias stable 17.0.6` registers an alias into `$XDG_CONFIG_HOME/llvmenv/aliases.toml`,
+//! and `.llvmenv` files can refer to the build by `stable`:
+//!
+//! ```toml
+//! [aliases]
+//! stable = "17.0.6"
+//! next = "my-trunk"
+//! team = "stable"  # aliases can be chained
+//! ```
+
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs};
+
+use crate::{build::Build, config::*, error::*};
+
+pub const ALIASES_TOML: &str = "aliases.toml";
+
+/// Registry of aliases, from alias to build name or another alias
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Aliases {
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn load() -> Result<Self> {
+        let path = config_dir()?.join(ALIASES_TOML);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(&path).with(&path)?)?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = config_dir()?.join(ALIASES_TOML);
+        fs::write(&path, toml::to_string_pretty(self)?).with(&path)
+    }
+
+    /// Follow the chain of aliases. A name which is not an alias is returned as is.
+    ///
+    /// ```
+    /// use llvmenv::alias::Aliases;
+    /// let mut aliases = Aliases::default();
+    /// aliases.aliases.insert("team".into(), "stable".into());
+    /// aliases.aliases.insert("stable".into(), "17.0.6".into());
+    /// assert_eq!(aliases.resolve("team").unwrap(), "17.0.6");
+    /// assert_eq!(aliases.resolve("my-trunk").unwrap(), "my-trunk");
+    ///
+    /// aliases.aliases.insert("17.0.6".into(), "team".into());
+    /// assert!(aliases.resolve("team").is_err());
+    /// ```
+    pub fn resolve(&self, name: &str) -> Result<String> {
+        let mut chain = vec![name.to_string()];
+        let mut current = name;
+        while let Some(next) = self.aliases.get(current) {
+            let cycle = chain.contains(next);
+            chain.push(next.clone());
+            if cycle {
+                return Err(Error::AliasCycle {
+                    chain: chain.join(" -> "),
+                });
+            }
+            current = next;
+        }
+        Ok(current.to_string())
+    }
+
+    /// Aliases which resolve into the build
+    pub fn of(&self, build: &str) -> Vec<&str> {
+        self.aliases
+            .keys()
+            .filter(|alias| self.resolve(alias).ok().as_deref() == Some(build))
+            .map(|alias| alias.as_str())
+            .collect()
+    }
+}
+
+/// Register an alias. The target has to be an existing build or alias.
+pub fn set_alias(alias: &str, target: &str) -> Result<()> {
+    let invalid = |message: &str| Error::InvalidAlias {
+        alias: alias.into(),
+        message: message.into(),
+    };
+    // Alias is written into shell commands by `llvmenv shell`
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_.+@".contains(c);
+    if alias.is_empty() || !alias.chars().all(safe) {
+        return Err(invalid(
+            "Name must not be empty and consist of alphanumerics and -_.+@",
+        ));
+    }
+    let mut aliases = Aliases::load()?;
+    if !aliases.aliases.contains_key(alias) && Build::from_name(alias)?.exists() {
+        return Err(invalid("Build of the same name exists"));
+    }
+    aliases.aliases.insert(alias.into(), target.into());
+    let resolved = aliases.resolve(alias)?;
+    if !Build::from_name(&resolved)?.exists() {
+        return Err(invalid(&format!("Build {} does not exist", resolved)));
+    }
+    aliases.save()
+}
+
+pub fn remove_alias(alias: &str) -> Result<()> {
+    let mut aliases = Aliases::load()?;
+    if aliases.aliases.remove(alias).is_none() {
+        return Err(Error::InvalidAlias {
+            alias: alias.into(),
+            message: "Not an alias".into(),
+        });
+    }
+    aliases.save()
+}
@@ -3,7 +3,7 @@ use llvmenv::error::FileIoConvert;
 use llvmenv::*;
 use llvmenv::{config::cache_dir, error::CommandExt};
 
-use log::{error, info};
+use log::{error, info, warn};
 use simplelog::*;
 use std::{
     env,
@@ -23,7 +23,27 @@ enum LLVMEnv {
     Init {},
 
     #[structopt(name = "builds", about = "List usable build")]
-    Builds {},
+    Builds {
+        #[structopt(
+            short = "l",
+            long = "long",
+            help = "Show version, build type, install date, size, origin and whether it is active"
+        )]
+        long: bool,
+        #[structopt(
+            long = "sort",
+            requires = "long",
+            possible_values = &["name", "version", "date", "size"],
+            help = "Sort key of --long, name by default. version, date and size are sorted in descending order"
+        )]
+        sort: Option<String>,
+        #[structopt(
+            long = "installed-from",
+            requires = "long",
+            help = "Show only builds compiled from the entry"
+        )]
+        installed_from: Option<String>,
+    },
 
     #[structopt(
         name = "clean",
@@ -36,6 +56,133 @@ enum LLVMEnv {
         name: Option<String>,
     },
 
+    #[structopt(
+        name = "which",
+        about = "Print the path of a tool in the current build"
+    )]
+    Which {
+        tool: String,
+        #[structopt(
+            short = "b",
+            long = "build",
+            help = "Use this build instead of the current one"
+        )]
+        build: Option<String>,
+    },
+
+    #[structopt(
+        name = "exec",
+        about = "Run a command with PATH, LD_LIBRARY_PATH and LLVM variables of a build, e.g. llvmenv exec -- clang --version"
+    )]
+    Exec {
+        #[structopt(
+            short = "b",
+            long = "build",
+            help = "Use this build instead of the current one"
+        )]
+        build: Option<String>,
+        #[structopt(required = true)]
+        command: Vec<String>,
+    },
+
+    #[structopt(
+        name = "rehash",
+        about = "Generate shims of the tools of all builds, to be used by putting the shims directory in PATH"
+    )]
+    Rehash {},
+
+    #[structopt(name = "shim-exec", setting = structopt::clap::AppSettings::Hidden)]
+    ShimExec { tool: String, args: Vec<String> },
+
+    #[structopt(
+        name = "shell",
+        about = "Print shell code to use a build in this shell session, e.g. eval \"$(llvmenv shell 17.0.6)\""
+    )]
+    Shell {
+        name: Option<String>,
+        #[structopt(
+            long = "unset",
+            help = "Print shell code to go back to .llvmenv settings"
+        )]
+        unset: bool,
+    },
+
+    #[structopt(
+        name = "alias",
+        about = "Set, show, remove (--rm) or list (--list) aliases of builds"
+    )]
+    Alias {
+        alias: Option<String>,
+        #[structopt(help = "Build or alias which the alias points at")]
+        target: Option<String>,
+        #[structopt(long = "rm", help = "Remove the alias")]
+        rm: bool,
+        #[structopt(short = "l", long = "list", help = "List all aliases")]
+        list: bool,
+    },
+
+    #[structopt(
+        name = "link",
+        about = "Register an LLVM installed outside of llvmenv, e.g. /usr/lib/llvm-14"
+    )]
+    Link {
+        name: String,
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+
+    #[structopt(name = "unlink", about = "Unregister a build linked by llvmenv link")]
+    Unlink {
+        name: String,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "Unlink even if global or local setting points at the build"
+        )]
+        force: bool,
+    },
+
+    #[structopt(
+        name = "install-missing",
+        about = "Install the build named by .llvmenv of the current directory if it is missing"
+    )]
+    InstallMissing {
+        #[structopt(
+            long = "binary-only",
+            help = "Install only from an archive of binary_url"
+        )]
+        binary_only: bool,
+    },
+
+    #[structopt(
+        name = "uninstall",
+        about = "Remove an installed build and its build directory"
+    )]
+    Uninstall {
+        name: String,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "Uninstall even if global or local setting points at the build"
+        )]
+        force: bool,
+    },
+
+    #[structopt(
+        name = "gc",
+        about = "Remove orphaned caches, build trees of installed entries, old sources and logs, and incomplete or orphaned builds"
+    )]
+    Gc {
+        #[structopt(long = "dry-run", help = "Only report what would be removed")]
+        dry_run: bool,
+        #[structopt(
+            long = "older-than",
+            default_value = "30",
+            help = "Days after which downloaded sources and logs are removed"
+        )]
+        older_than: u64,
+    },
+
     #[structopt(name = "entries", about = "List entries to be built")]
     Entries {},
     #[structopt(name = "build-entry", about = "Build LLVM/Clang")]
@@ -58,7 +205,11 @@ enum LLVMEnv {
             help = "discard source directory for remote resources"
         )]
         discard: bool,
-        #[structopt(short = "j", long = "nproc")]
+        #[structopt(
+            short = "j",
+            long = "nproc",
+            help = "Number of parallel compile jobs (default: decided from CPUs and available memory)"
+        )]
         nproc: Option<usize>,
         #[structopt(
             short = "t",
@@ -66,12 +217,127 @@ enum LLVMEnv {
             help = "Overwrite cmake build type (Debug, Release, RelWithDebInfo, or MinSizeRel)"
         )]
         build_type: Option<entry::BuildType>,
+        #[structopt(
+            long = "dry-run",
+            help = "Print the resources, directories and commands of the build without running them"
+        )]
+        dry_run: bool,
+        #[structopt(
+            long = "json",
+            requires = "dry-run",
+            help = "Print the dry-run plan as JSON"
+        )]
+        json: bool,
+        #[structopt(
+            long = "reconfigure",
+            help = "Run cmake configure even if its inputs are unchanged"
+        )]
+        reconfigure: bool,
+        #[structopt(
+            long = "skip-preflight",
+            help = "Build even if the preflight check of the environment fails"
+        )]
+        skip_preflight: bool,
+        #[structopt(long = "no-verify", help = "Skip smoke tests after install")]
+        no_verify: bool,
+    },
+
+    #[structopt(
+        name = "verify",
+        about = "Run smoke tests of the build, e.g. compile and run hello-world"
+    )]
+    Verify { name: String },
+
+    #[structopt(
+        name = "test",
+        about = "Run lit test suites (check-* targets) in the build directory of an entry"
+    )]
+    Test {
+        name: String,
+        #[structopt(
+            short = "s",
+            long = "suite",
+            default_value = "check-llvm",
+            help = "Check target to run, e.g. check-clang or check-lld. Can be repeated"
+        )]
+        suites: Vec<String>,
+        #[structopt(
+            short = "j",
+            long = "nproc",
+            help = "Number of parallel compile jobs (default: decided from CPUs and available memory)"
+        )]
+        nproc: Option<usize>,
+    },
+
+    #[structopt(
+        name = "add-runtime",
+        about = "Build compiler-rt builtins (and libc++) for another target by the clang of a build"
+    )]
+    AddRuntime {
+        name: String,
+        #[structopt(
+            long = "target",
+            help = "Target triple, e.g. wasm32-wasi or arm-none-eabi"
+        )]
+        target: String,
+        #[structopt(long = "sysroot", help = "Sysroot of the target", parse(from_os_str))]
+        sysroot: Option<PathBuf>,
+        #[structopt(long = "libcxx", help = "Also build libunwind, libc++abi and libc++")]
+        libcxx: bool,
+        #[structopt(
+            short = "D",
+            long = "define",
+            parse(try_from_str = runtime::parse_define),
+            number_of_values = 1,
+            help = "Additional cmake option KEY=VALUE"
+        )]
+        define: Vec<(String, String)>,
+        #[structopt(
+            short = "j",
+            long = "nproc",
+            help = "Number of parallel compile jobs (default: decided from CPUs and available memory)"
+        )]
+        nproc: Option<usize>,
+    },
+
+    #[structopt(
+        name = "doctor",
+        about = "Check build tools, compilers and disk space required to build an entry"
+    )]
+    Doctor {
+        #[structopt(help = "Entry to be checked. Only the host tools are checked if omitted")]
+        name: Option<String>,
+    },
+
+    #[structopt(
+        name = "logs",
+        about = "Show the captured output of the latest build-entry run"
+    )]
+    Logs {
+        entry: String,
+        #[structopt(
+            short = "p",
+            long = "phase",
+            help = "Show only the log of this phase, e.g. configure, build, or stage1-build"
+        )]
+        phase: Option<String>,
+        #[structopt(
+            short = "f",
+            long = "follow",
+            help = "Keep printing the output while the build is running"
+        )]
+        follow: bool,
     },
 
     #[structopt(name = "current", about = "Show the name of current build")]
     Current {
         #[structopt(short = "v", long = "verbose")]
         verbose: bool,
+        #[structopt(
+            long = "explain",
+            help = "Show every examined setting and why it is accepted or rejected"
+        )]
+        explain: bool,
     },
     #[structopt(name = "prefix", about = "Show the prefix of the current build")]
     Prefix {
@@ -166,12 +432,87 @@ fn main() -> error::Result<()> {
             }
         }
 
-        LLVMEnv::Builds {} => {
+        LLVMEnv::Builds {
+            long: true,
+            sort,
+            installed_from,
+        } => {
+            let infos = build::arrange_infos(
+                build::builds()?.iter().map(|b| b.info()).collect(),
+                installed_from.as_deref(),
+                sort.as_deref().unwrap_or("name"),
+            );
+            // Without side effects of auto_install
+            let current = build::explain_build(&mut Vec::new()).ok();
+            let rows: Vec<[String; 8]> = infos
+                .iter()
+                .map(|info| {
+                    let active = current.as_ref().filter(|b| b.name() == info.name);
+                    [
+                        if active.is_some() { "*" } else { " " }.to_string(),
+                        info.name.clone(),
+                        info.version
+                            .as_ref()
+                            .map(|v| v.to_string())
+                            .unwrap_or_else(|| "-".into()),
+                        info.build_type.clone().unwrap_or_else(|| "-".into()),
+                        info.installed_at
+                            .as_ref()
+                            .map(|t| t.chars().take(16).collect::<String>().replace('T', " "))
+                            .unwrap_or_else(|| "-".into()),
+                        info.size.map(gc::format_size).unwrap_or_else(|| "-".into()),
+                        info.origin.clone(),
+                        match active.and_then(|b| b.set_by()) {
+                            Some(setting) => {
+                                format!("{} (set by {})", info.prefix.display(), setting)
+                            }
+                            None => info.prefix.display().to_string(),
+                        },
+                    ]
+                })
+                .collect();
+            let header = [
+                "",
+                "NAME",
+                "VERSION",
+                "TYPE",
+                "INSTALLED",
+                "SIZE",
+                "ORIGIN",
+                "PREFIX",
+            ];
+            let mut widths = header.map(|h| h.len());
+            for row in &rows {
+                for (w, cell) in widths.iter_mut().zip(row) {
+                    *w = (*w).max(cell.len());
+                }
+            }
+            for row in std::iter::once(header.map(String::from)).chain(rows) {
+                let line: Vec<String> = row
+                    .iter()
+                    .zip(widths)
+                    .map(|(cell, w)| format!("{:<w$}", cell, w = w))
+                    .collect();
+                println!("{}", line.join("  ").trim_end());
+            }
+        }
+
+        LLVMEnv::Builds { .. } => {
             let builds = build::builds()?;
+            let aliases = alias::Aliases::load()?;
             let max = builds.iter().map(|b| b.name().len()).max().unwrap();
             for b in &builds {
+                let mut foreign = match b.foreign_host() {
+                    Some(host) => format!(" (foreign host: {})", host),
+                    None if b.is_linked() => " (linked)".to_string(),
+                    None => String::new(),
+                };
+                let names = aliases.of(b.name());
+                if !names.is_empty() {
+                    foreign += &format!(" (alias: {})", names.join(", "));
+                }
                 println!(
-                    "{name:<width$}: {prefix}",
+                    "{name:<width$}: {prefix}{foreign}",
                     name = b.name(),
                     prefix = b.prefix().display(),
                     width = max
@@ -179,6 +520,211 @@ fn main() -> error::Result<()> {
             }
         }
 
+        LLVMEnv::Which { tool, build } => {
+            let build = match build {
+                Some(name) => get_existing_build(&name),
+                None => or_exit(build::seek_build()),
+            };
+            match exec::which(&build, &tool) {
+                Some(path) => println!("{}", path.display()),
+                None => {
+                    let providers: Vec<String> = exec::providers(&tool)?
+                        .iter()
+                        .map(|b| b.name().to_string())
+                        .collect();
+                    if providers.is_empty() {
+                        eprintln!("{} is not found in any build", tool);
+                    } else {
+                        eprintln!(
+                            "{} is not found in {}, but provided by: {}",
+                            tool,
+                            build.name(),
+                            providers.join(", ")
+                        );
+                    }
+                    exit(1)
+                }
+            }
+        }
+
+        LLVMEnv::Exec { build, command } => {
+            let build = match build {
+                Some(name) => get_existing_build(&name),
+                None => or_exit(build::seek_build()),
+            };
+            let status = or_exit(exec::command(&build, &command[0], &command[1..]))
+                .status()
+                .unwrap_or_else(|e| {
+                    error!("Failed to run {}: {}", command[0], e);
+                    exit(127)
+                });
+            exit(status.code().unwrap_or(1))
+        }
+
+        LLVMEnv::Rehash {} => {
+            let n = or_exit(shim::rehash());
+            println!(
+                "{} shims in {}. Add it to PATH to use them",
+                n,
+                shim::shims_dir()?.display()
+            );
+        }
+
+        LLVMEnv::ShimExec { tool, args } => {
+            let (build, path) = shim::resolve(&tool).unwrap_or_else(|e| {
+                eprintln!("llvmenv: {}", e);
+                exit(127)
+            });
+            let mut command = or_exit(exec::command(&build, &path.display().to_string(), &args));
+            #[cfg(unix)]
+            {
+                let e = std::os::unix::process::CommandExt::exec(&mut command);
+                eprintln!("llvmenv: Failed to execute {}: {}", path.display(), e);
+                exit(126)
+            }
+            #[cfg(not(unix))]
+            {
+                let status = command.status()?;
+                exit(status.code().unwrap_or(1))
+            }
+        }
+
+        LLVMEnv::Shell { name, unset } => {
+            let fish = env::var("SHELL").is_ok_and(|shell| shell.ends_with("fish"));
+            match (name, unset) {
+                (Some(name), false) => {
+                    // Check the build exists and runs here
+                    or_exit(get_existing_build(&name).check_host());
+                    // The output is evaluated by the shell
+                    if fish {
+                        println!(
+                            "set -gx {} {}",
+                            build::LLVMENV_BUILD,
+                            plan::fish_quote(&name)
+                        );
+                    } else {
+                        println!(
+                            "export {}={}",
+                            build::LLVMENV_BUILD,
+                            plan::shell_quote(&name)
+                        );
+                    }
+                }
+                (None, true) => {
+                    if fish {
+                        println!("set -e {}", build::LLVMENV_BUILD);
+                    } else {
+                        println!("unset {}", build::LLVMENV_BUILD);
+                    }
+                }
+                _ => {
+                    error!("Either a build name or --unset is required");
+                    exit(1)
+                }
+            }
+        }
+
+        LLVMEnv::Alias {
+            alias: name,
+            target,
+            rm,
+            list,
+        } => match (name, target) {
+            (Some(name), None) if rm => or_exit(alias::remove_alias(&name)),
+            (Some(name), Some(target)) if !rm && !list => or_exit(alias::set_alias(&name, &target)),
+            (Some(name), None) if !list => {
+                let aliases = alias::Aliases::load()?;
+                match aliases.aliases.get(&name) {
+                    Some(_) => println!("{}", or_exit(aliases.resolve(&name))),
+                    None => {
+                        error!("{} is not an alias", name);
+                        exit(1)
+                    }
+                }
+            }
+            (None, None) if !rm => {
+                let aliases = alias::Aliases::load()?;
+                for (name, target) in &aliases.aliases {
+                    match aliases.resolve(name) {
+                        Ok(build) if &build != target => {
+                            println!("{} -> {} ({})", name, target, build)
+                        }
+                        Ok(_) => println!("{} -> {}", name, target),
+                        Err(e) => println!("{} -> {} ({})", name, target, e),
+                    }
+                }
+            }
+            _ => {
+                error!("Invalid combination of arguments, see `llvmenv alias --help`");
+                exit(1)
+            }
+        },
+
+        LLVMEnv::Link { name, path } => {
+            or_exit(link::link(&name, &path));
+            shim::rehash_if_enabled()?;
+        }
+        LLVMEnv::Unlink { name, force } => {
+            or_exit(link::unlink(&name, force));
+        }
+
+        LLVMEnv::InstallMissing { binary_only } => {
+            let mut trace = Vec::new();
+            let current = build::explain_build(&mut trace);
+            match build::first_missing(&trace) {
+                Some((name, source)) => {
+                    info!("{} names {}, which is not installed", source, name);
+                    let config = config::load_config()?.auto_install;
+                    let binary_only =
+                        binary_only || config.mode == Some(install::InstallMode::Binary);
+                    let build = or_exit(install::install(name, &config, binary_only));
+                    println!(
+                        "Installed {} into {}",
+                        build.name(),
+                        build.prefix().display()
+                    );
+                }
+                None => {
+                    let current = or_exit(current);
+                    println!("Nothing is missing: {} is used", current.name());
+                }
+            }
+        }
+
+        LLVMEnv::Uninstall { name, force } => {
+            let build = get_existing_build(&name);
+            or_exit(build.uninstall(force));
+        }
+
+        LLVMEnv::Gc {
+            dry_run,
+            older_than,
+        } => {
+            let garbage = or_exit(gc::find(std::time::Duration::from_secs(
+                older_than * 24 * 60 * 60,
+            )));
+            let mut totals: std::collections::BTreeMap<gc::Kind, u64> = Default::default();
+            for g in &garbage {
+                println!(
+                    "{:>10}  {:<18} {}",
+                    gc::format_size(g.size),
+                    g.kind.to_string(),
+                    g.path.display()
+                );
+                *totals.entry(g.kind).or_default() += g.size;
+            }
+            for (kind, size) in &totals {
+                println!("{:>10}  total of {}", gc::format_size(*size), kind);
+            }
+            let total: u64 = totals.values().sum();
+            if dry_run {
+                println!("{} would be reclaimed", gc::format_size(total));
+            } else {
+                let reclaimed = gc::remove(&garbage)?;
+                println!("{} reclaimed", gc::format_size(reclaimed));
+            }
+        }
+
         LLVMEnv::Entries {} => {
             if let Ok(entries) = entry::load_entries() {
                 for entry in &entries {
@@ -195,22 +741,53 @@ fn main() -> error::Result<()> {
             builder,
             nproc,
             build_type,
+            dry_run,
+            json,
+            reconfigure,
+            skip_preflight,
+            no_verify,
         } => {
             let mut entry = entry::load_entry(&name)?;
-            let nproc = nproc.unwrap_or_else(num_cpus::get);
             if let Some(builder) = builder {
                 entry.set_builder(&builder)?;
             }
             if let Some(build_type) = build_type {
                 entry.set_build_type(build_type)?;
             }
+            let jobs_config = config::load_config()?.jobs;
 
-            let bdir = match entry {
-                llvmenv::entry::Entry::Remote { ref name, .. } => {
-                    dirs::cache_dir().unwrap().join(format!("llvmenv/{}", name))
+            if dry_run {
+                // Nothing but the plan is written into stdout
+                let jobs = jobs::Parallelism::estimate(entry.build_type(), &jobs_config, nproc);
+                let plan = entry.plan(&jobs, update, discard, reconfigure)?;
+                if json {
+                    println!("{}", plan.to_json()?);
+                } else {
+                    print!("{}", plan);
                 }
-                llvmenv::entry::Entry::Local { ref path, .. } => path.into(),
-            };
+                return Ok(());
+            }
+            let jobs = jobs::Parallelism::detect(entry.build_type(), &jobs_config, nproc);
+
+            let checks = doctor::preflight(&entry)?;
+            for check in &checks {
+                match check.status {
+                    doctor::Status::Ok => {}
+                    doctor::Status::Warning => warn!("{}", check),
+                    doctor::Status::Error => error!("{}", check),
+                }
+            }
+            if checks.iter().any(|c| c.status == doctor::Status::Error) {
+                if skip_preflight {
+                    warn!("Preflight check failed, but continue by --skip-preflight");
+                } else {
+                    error!("Preflight check failed. Run `llvmenv doctor {}` for detail, or use --skip-preflight", entry.name());
+                    exit(1);
+                }
+            }
+
+            let log = logs::BuildLog::create(entry.name())?;
+            let bdir = entry.source_root()?;
             if discard {
                 // dir may or may not exist yet we dont want to error if it does not
                 if bdir.exists() {
@@ -225,37 +802,190 @@ fn main() -> error::Result<()> {
             if bdir.exists() {
                 info!("source directory already exists, so skiping checkout");
             } else {
-                entry.checkout().unwrap();
+                or_exit(entry.checkout(Some(&log)));
             }
             if update {
                 info!("updating source, by checking for required resources!");
-                entry.update().unwrap();
+                or_exit(entry.update(Some(&log)));
             }
 
-            entry.build(nproc).unwrap();
+            or_exit(entry.build(&jobs, reconfigure, Some(&log)));
+            shim::rehash_if_enabled()?;
 
             // discarding the initial source directory should be default behavior (unless otherwise specified by the user)
             // TODO: Add a flag to keep the source directory here
             if discard && bdir.exists() {
                 std::fs::remove_dir_all(bdir).with(bdir).unwrap();
             }
+
+            if !no_verify {
+                let build = build::Build::from_path(&entry.prefix()?);
+                info!("Verify the installed build: {}", build.prefix().display());
+                let report = verify::verify_build(&build)?;
+                print_report(&report);
+                if !report.passed() {
+                    error!(
+                        "Smoke tests failed. The build is installed at {}, but may be broken",
+                        build.prefix().display()
+                    );
+                    exit(1);
+                }
+            }
+        }
+
+        LLVMEnv::Verify { name } => {
+            let build = get_existing_build(&name);
+            let report = verify::verify_build(&build)?;
+            print_report(&report);
+            if !report.passed() {
+                exit(1);
+            }
+        }
+
+        LLVMEnv::Test {
+            name,
+            suites,
+            nproc,
+        } => {
+            let entry = entry::load_entry(&name)?;
+            let jobs =
+                jobs::Parallelism::detect(entry.build_type(), &config::load_config()?.jobs, nproc);
+            let results = or_exit(lit::run_suites(&entry, &suites, &jobs));
+            let mut failed = false;
+            for suite in &suites {
+                let result = &results.suites[suite];
+                let mark = if result.succeeded && result.summary.ok() {
+                    " ok "
+                } else {
+                    failed = true;
+                    "FAIL"
+                };
+                println!("[{}] {}: {}", mark, suite, result.summary);
+            }
+            info!(
+                "Results are saved in {}",
+                lit::TestResults::path(entry.name())?.display()
+            );
+            if failed {
+                exit(1);
+            }
+        }
+
+        LLVMEnv::AddRuntime {
+            name,
+            target,
+            sysroot,
+            libcxx,
+            define,
+            nproc,
+        } => {
+            let build = get_existing_build(&name);
+            let jobs = jobs::Parallelism::detect(
+                entry::BuildType::Release,
+                &config::load_config()?.jobs,
+                nproc,
+            );
+            let setting = runtime::RuntimeSetting {
+                target,
+                sysroot,
+                libcxx,
+                option: define.into_iter().collect(),
+            };
+            let log = logs::BuildLog::create(build.name())?;
+            let record = or_exit(runtime::add_runtime(&build, &setting, &jobs, Some(&log)));
+            println!(
+                "Installed {} for {} into {}",
+                record.components.join(", "),
+                record.target,
+                build.prefix().display()
+            );
+        }
+
+        LLVMEnv::Doctor { name } => {
+            let checks = match name {
+                Some(name) => doctor::preflight(&entry::load_entry(&name)?)?,
+                None => doctor::check_host(None, &entry::CMakeGenerator::default()),
+            };
+            for check in &checks {
+                println!("{}", check);
+            }
+            if checks.iter().any(|c| c.status == doctor::Status::Error) {
+                exit(1);
+            }
         }
 
-        LLVMEnv::Current { verbose } => {
-            let build = build::seek_build()?;
-            println!("{}", build.name());
+        LLVMEnv::Logs {
+            entry,
+            phase,
+            follow,
+        } => {
+            let run = match logs::latest(&entry)? {
+                Some(run) => run,
+                None => {
+                    eprintln!("No logs found for entry '{}'", entry);
+                    exit(1)
+                }
+            };
+            let files = match phase {
+                Some(ref phase) => vec![run.join(format!("{}.log", phase))],
+                None => logs::phase_logs(&run)?,
+            };
+            if follow {
+                // Follow the latest phase, and the phases after it
+                match (&phase, files.last()) {
+                    (Some(_), _) => logs::follow(&files[0], None)?,
+                    (None, Some(current)) => logs::follow(current, Some(&run))?,
+                    (None, None) => {
+                        eprintln!("No logs found in {}", run.display());
+                        exit(1)
+                    }
+                }
+            } else {
+                for file in &files {
+                    if files.len() > 1 {
+                        println!("==> {} <==", file.display());
+                    }
+                    print!("{}", std::fs::read_to_string(file).with(file)?);
+                }
+            }
+        }
+
+        LLVMEnv::Current { explain: true, .. } => {
+            let mut trace = Vec::new();
+            let build = build::explain_build(&mut trace);
+            for candidate in &trace {
+                match &candidate.content {
+                    Some(content) => println!(
+                        "{}: \"{}\" -> {}",
+                        candidate.source, content, candidate.verdict
+                    ),
+                    None => println!("{}: {}", candidate.source, candidate.verdict),
+                }
+            }
+            let build = or_exit(build);
+            match build.set_by() {
+                Some(setting) => println!("=> {} (set by {})", build.name(), setting),
+                None => println!("=> {} (no setting is found)", build.name()),
+            }
+        }
+        LLVMEnv::Current { verbose, .. } => {
+            let build = or_exit(build::seek_build());
+            match build.alias() {
+                Some(alias) => println!("{} ({})", build.name(), alias),
+                None => println!("{}", build.name()),
+            }
             if verbose {
-                if let Some(env) = build.env_path() {
-                    eprintln!("set by {}", env.display());
+                if let Some(setting) = build.set_by() {
+                    eprintln!("set by {}", setting);
                 }
             }
         }
         LLVMEnv::Prefix { verbose } => {
-            let build = build::seek_build()?;
+            let build = or_exit(build::seek_build());
             println!("{}", build.prefix().display());
             if verbose {
-                if let Some(env) = build.env_path() {
-                    eprintln!("set by {}", env.display());
+                if let Some(setting) = build.set_by() {
+                    eprintln!("set by {}", setting);
                 }
             }
         }
@@ -268,7 +998,7 @@ fn main() -> error::Result<()> {
             let build = if let Some(name) = name {
                 get_existing_build(&name)
             } else {
-                build::seek_build()?
+                or_exit(build::seek_build())
             };
             let version = build.version()?;
             if !(major || minor || patch) {
@@ -289,12 +1019,12 @@ fn main() -> error::Result<()> {
 
         LLVMEnv::Global { name } => {
             let build = get_existing_build(&name);
-            build.set_global()?;
+            or_exit(build.set_global());
         }
         LLVMEnv::Local { name, path } => {
             let build = get_existing_build(&name);
             let path = path.unwrap_or_else(|| env::current_dir().unwrap());
-            build.set_local(&path)?;
+            or_exit(build.set_local(&path));
         }
 
         LLVMEnv::Archive { name, verbose } => {
@@ -354,7 +1084,7 @@ fn main() -> error::Result<()> {
 }
 
 fn get_existing_build(name: &str) -> build::Build {
-    let build = build::Build::from_name(name).unwrap();
+    let build = or_exit(build::Build::from_name(name));
     if build.exists() {
         build
     } else {
@@ -362,3 +1092,17 @@ fn get_existing_build(name: &str) -> build::Build {
         exit(1)
     }
 }
+
+fn print_report(report: &verify::VerifyReport) {
+    for test in &report.tests {
+        println!("{}", test);
+    }
+}
+
+/// Print the error message and exit, instead of panicking with its debug representation
+fn or_exit<T>(result: error::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(1)
+    })
+}
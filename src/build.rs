@@ -4,23 +4,89 @@ use glob::glob;
 use log::*;
 use regex::Regex;
 use semver::Version;
+use serde_derive::{Deserialize, Serialize};
 use std::{
-    env, fs,
-    io::{self, Read, Write},
+    collections::BTreeSet,
+    env, fmt, fs,
+    io::{self, Write},
     path::{Path, PathBuf},
     process::Command,
+    sync::OnceLock,
 };
 
+use crate::alias::Aliases;
 use crate::config::*;
+use crate::entry::load_entry;
 use crate::error::*;
+use crate::gc::dir_size;
+use crate::install;
+use crate::link::Links;
+use crate::manifest::Manifest;
+use crate::selector::Selector;
 
 const LLVMENV_FN: &str = ".llvmenv";
+/// Compatible setting file used by other tools, which is read after `.llvmenv`
+const LLVM_VERSION_FN: &str = ".llvm-version";
 
-#[derive(Debug)]
+/// Environment variable which overrides `.llvmenv` files in the shell session
+pub const LLVMENV_BUILD: &str = "LLVMENV_BUILD";
+const LOCALS_TOML: &str = "locals.toml";
+
+#[derive(Debug, Clone)]
 pub struct Build {
     name: String,             // name and id of build
     prefix: PathBuf,          // the path where the LLVM build realy exists
     llvmenv: Option<PathBuf>, // path of .llvmenv
+    origin: Origin,
+    alias: Option<String>, // alias by which the build is selected
+    from_env: bool,        // selected by LLVMENV_BUILD
+}
+
+/// Summary of a build shown by `llvmenv builds --long`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildInfo {
+    pub name: String,
+    pub prefix: PathBuf,
+    pub version: Option<Version>,
+    pub build_type: Option<String>,
+    /// RFC 3339 timestamp recorded in the manifest
+    pub installed_at: Option<String>,
+    /// Disk usage in bytes. `None` for `/usr`.
+    pub size: Option<u64>,
+    /// e.g. "entry 17.0.6", "binary {archive}", "linked" or "system"
+    pub origin: String,
+    /// Entry which the build is compiled from
+    pub entry: Option<String>,
+}
+
+/// Keep the builds compiled from the entry `installed_from`, and sort them by `sort`:
+/// `version`, `date` and `size` in descending order. Otherwise the order is kept.
+pub fn arrange_infos(
+    mut infos: Vec<BuildInfo>,
+    installed_from: Option<&str>,
+    sort: &str,
+) -> Vec<BuildInfo> {
+    if let Some(entry) = installed_from {
+        infos.retain(|info| info.entry.as_deref() == Some(entry));
+    }
+    match sort {
+        "version" => infos.sort_by(|a, b| b.version.cmp(&a.version)),
+        "date" => infos.sort_by(|a, b| b.installed_at.cmp(&a.installed_at)),
+        "size" => infos.sort_by_key(|info| std::cmp::Reverse(info.size)),
+        _ => {}
+    }
+    infos
+}
+
+/// Where a build comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    /// Installed by llvmenv into the data dir
+    Llvmenv,
+    /// Installed by the package manager of the system
+    System,
+    /// Registered by `llvmenv link`
+    Linked,
 }
 
 impl Build {
@@ -29,6 +95,9 @@ impl Build {
             name: "system".into(),
             prefix: PathBuf::from("/usr"),
             llvmenv: None,
+            origin: Origin::System,
+            alias: None,
+            from_env: false,
         }
     }
 
@@ -38,20 +107,88 @@ impl Build {
             name: name.into(),
             prefix: path.to_owned(),
             llvmenv: None,
+            origin: Origin::Llvmenv,
+            alias: None,
+            from_env: false,
         }
     }
 
+    /// Build installed outside of llvmenv, see [link](../link/index.html) module
+    pub fn linked(name: &str, prefix: &Path) -> Self {
+        Build {
+            name: name.into(),
+            prefix: prefix.to_owned(),
+            llvmenv: None,
+            origin: Origin::Linked,
+            alias: None,
+            from_env: false,
+        }
+    }
+
+    /// Side-by-side install of the system, e.g. `system-14` in `/usr/lib/llvm-14`
+    fn system_version(major: u64, prefix: &Path) -> Self {
+        Build {
+            name: format!("system-{}", major),
+            prefix: prefix.to_owned(),
+            llvmenv: None,
+            origin: Origin::System,
+            alias: None,
+            from_env: false,
+        }
+    }
+
+    /// Build of the name, which may be an [alias](../alias/index.html)
     pub fn from_name(name: &str) -> Result<Self> {
+        let resolved = Aliases::load()?.resolve(name)?;
+        let mut build = Self::from_resolved_name(&resolved)?;
+        if resolved != name {
+            build.alias = Some(name.into());
+        }
+        Ok(build)
+    }
+
+    fn from_resolved_name(name: &str) -> Result<Self> {
         if name == "system" {
             return Ok(Self::system());
         }
+        if name.starts_with("system-") {
+            if let Some(build) = system_builds().into_iter().find(|b| b.name == name) {
+                return Ok(build);
+            }
+        }
+        if let Some(prefix) = Links::load()?.links.get(name) {
+            return Ok(Self::linked(name, prefix));
+        }
         Ok(Build {
             name: name.into(),
             prefix: data_dir()?.join(name),
             llvmenv: None,
+            origin: Origin::Llvmenv,
+            alias: None,
+            from_env: false,
         })
     }
 
+    /// Alias by which this build is selected
+    pub fn alias(&self) -> Option<&str> {
+        self.alias.as_deref()
+    }
+
+    /// Whether this build is registered by `llvmenv link`
+    pub fn is_linked(&self) -> bool {
+        self.origin == Origin::Linked
+    }
+
+    /// Whether this is a build of the system, e.g. `system` or `system-14`, which is not managed by llvmenv
+    pub fn is_system(&self) -> bool {
+        self.origin == Origin::System
+    }
+
+    /// Whether this build is installed by llvmenv, and can be modified by it
+    pub fn is_managed(&self) -> bool {
+        self.origin == Origin::Llvmenv
+    }
+
     pub fn exists(&self) -> bool {
         self.prefix.is_dir()
     }
@@ -71,15 +208,146 @@ impl Build {
         }
     }
 
+    /// Where this build is selected, e.g. the path of `.llvmenv` or `LLVMENV_BUILD`
+    pub fn set_by(&self) -> Option<String> {
+        if self.from_env {
+            return Some(LLVMENV_BUILD.into());
+        }
+        self.env_path().map(|path| path.display().to_string())
+    }
+
+    /// Summary of this build for `llvmenv builds --long`
+    pub fn info(&self) -> BuildInfo {
+        let manifest = Manifest::load(&self.prefix).ok().flatten();
+        let origin = match self.origin {
+            Origin::System => "system".to_string(),
+            Origin::Linked => "linked".to_string(),
+            Origin::Llvmenv => match &manifest {
+                Some(Manifest {
+                    archive: Some(archive),
+                    ..
+                }) => format!("binary {}", archive),
+                Some(Manifest {
+                    entry: Some(entry), ..
+                }) => format!("entry {}", entry),
+                _ => "unknown".to_string(),
+            },
+        };
+        let manifest = manifest.unwrap_or_default();
+        BuildInfo {
+            name: self.name.clone(),
+            prefix: self.prefix.clone(),
+            version: self.version().ok(),
+            build_type: manifest.build_type,
+            installed_at: manifest.installed_at,
+            // Other files are in /usr
+            size: if self.prefix == Path::new("/usr") {
+                None
+            } else {
+                Some(dir_size(&self.prefix))
+            },
+            origin,
+            entry: manifest.entry,
+        }
+    }
+
+    /// Host triple if this build is cross-compiled for another machine
+    pub fn foreign_host(&self) -> Option<String> {
+        Manifest::load(&self.prefix).ok()??.foreign_host
+    }
+
     pub fn set_global(&self) -> Result<()> {
         self.set_local(&config_dir()?)
     }
 
+    /// Error if this build cannot run on this machine
+    pub fn check_host(&self) -> Result<()> {
+        match self.foreign_host() {
+            Some(host) => Err(Error::ForeignHostBuild {
+                name: self.name.clone(),
+                host,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Write `.llvmenv` into the directory. Foreign-host builds are refused.
     pub fn set_local(&self, path: &Path) -> Result<()> {
+        self.check_host()?;
         let env = path.join(LLVMENV_FN);
         let mut f = fs::File::create(&env).with(&env)?;
-        write!(f, "{}", self.name).with(env)?;
+        // Keep the alias so that the setting follows it
+        write!(f, "{}", self.alias.as_ref().unwrap_or(&self.name)).with(&env)?;
         info!("Write setting to {}", path.display());
+        if path != config_dir()? {
+            let mut locals = Locals::load()?;
+            locals.paths.insert(env.canonicalize().with(&env)?);
+            locals.save()?;
+        }
+        Ok(())
+    }
+
+    /// Global and local `.llvmenv` files which point at this build
+    pub fn references(&self) -> Result<Vec<PathBuf>> {
+        let mut locals = Locals::load()?;
+        locals.prune();
+        let mut paths = vec![config_dir()?.join(LLVMENV_FN)];
+        paths.extend(locals.paths);
+        Ok(paths
+            .into_iter()
+            .filter(|path| {
+                // A foreign-host build is still referred, although it cannot be selected
+                read_env(path)
+                    .and_then(|content| match Selector::parse(&content).ok()?.select() {
+                        Ok(build) => Some(build.name),
+                        Err(Verdict::ForeignHost(name, _)) => Some(name),
+                        Err(_) => None,
+                    })
+                    .is_some_and(|name| name == self.name)
+            })
+            .collect())
+    }
+
+    /// Remove the prefix and the cached build dirs of this build
+    ///
+    /// Refused if `.llvmenv` files point at this build, unless `force` is set.
+    pub fn uninstall(&self, force: bool) -> Result<()> {
+        if self.is_system() {
+            return Err(Error::SystemBuild);
+        }
+        if self.is_linked() {
+            return Err(Error::LinkedBuild {
+                name: self.name.clone(),
+            });
+        }
+        let references = self.references()?;
+        if !references.is_empty() {
+            if !force {
+                return Err(Error::BuildInUse {
+                    name: self.name.clone(),
+                    paths: references,
+                });
+            }
+            for path in &references {
+                warn!("{} points at the uninstalled build", path.display());
+            }
+        }
+        for alias in Aliases::load()?.of(&self.name) {
+            warn!("Alias {} points at the uninstalled build", alias);
+        }
+        let entry = Manifest::load(&self.prefix)?
+            .and_then(|m| m.entry)
+            .unwrap_or_else(|| self.name.clone());
+        match load_entry(&entry).and_then(|entry| entry.clean_build_dir()) {
+            Ok(()) => {}
+            Err(e) => warn!("Build dir of entry {} is not removed: {}", entry, e),
+        }
+        let runtimes = cache_dir()?.join("runtimes").join(&self.name);
+        if runtimes.exists() {
+            fs::remove_dir_all(&runtimes).with(&runtimes)?;
+        }
+        info!("Remove {}", self.prefix.display());
+        fs::remove_dir_all(&self.prefix).with(&self.prefix)?;
         Ok(())
     }
 
@@ -126,54 +394,298 @@ fn local_builds() -> Result<Vec<Build>> {
         .collect())
 }
 
+fn linked_builds() -> Result<Vec<Build>> {
+    Ok(Links::load()?
+        .links
+        .iter()
+        .map(|(name, prefix)| Build::linked(name, prefix))
+        .collect())
+}
+
 pub fn builds() -> Result<Vec<Build>> {
     let mut bs = local_builds()?;
+    bs.append(&mut linked_builds()?);
     bs.sort_by(|a, b| a.name.cmp(&b.name));
-    bs.insert(0, Build::system());
-    Ok(bs)
-}
-
-fn load_local_env(path: &Path) -> Result<Option<Build>> {
-    let cand = path.join(LLVMENV_FN);
-    if !cand.exists() {
-        return Ok(None);
-    }
-    let mut f = fs::File::open(&cand).with(&cand)?;
-    let mut s = String::new();
-    f.read_to_string(&mut s).with(cand)?;
-    let name = s.trim();
-    let mut build = Build::from_name(name)?;
-    if build.exists() {
-        build.llvmenv = Some(path.into());
-        Ok(Some(build))
+    let mut system = system_builds();
+    system.insert(0, Build::system());
+    system.append(&mut bs);
+    Ok(system)
+}
+
+/// Patterns of the prefixes of side-by-side system installs
+const SYSTEM_PREFIXES: [&str; 3] = ["/usr/lib/llvm-*", "/usr/lib/llvm*", "/usr/lib64/llvm*"];
+
+/// Discover side-by-side system installs, e.g. `/usr/lib/llvm-14` of Debian
+/// or `llvm-config-15` in `PATH`, sorted by their major versions.
+/// They are discovered once in a process, since it runs `llvm-config` of each of them.
+fn system_builds() -> Vec<Build> {
+    static SYSTEM_BUILDS: OnceLock<Vec<Build>> = OnceLock::new();
+    SYSTEM_BUILDS
+        .get_or_init(|| {
+            let path = env::var_os("PATH").unwrap_or_default();
+            discover_system_builds(
+                &SYSTEM_PREFIXES,
+                &env::split_paths(&path).collect::<Vec<_>>(),
+            )
+        })
+        .clone()
+}
+
+fn discover_system_builds(patterns: &[&str], path: &[PathBuf]) -> Vec<Build> {
+    let mut prefixes: Vec<PathBuf> = patterns
+        .iter()
+        .flat_map(|pattern| glob(pattern).into_iter().flatten().flatten())
+        .filter(|prefix| prefix.join("bin/llvm-config").is_file())
+        .collect();
+    let config = Regex::new(r"^llvm-config-\d+$").unwrap();
+    for dir in path {
+        let files = match fs::read_dir(dir) {
+            Ok(files) => files,
+            Err(_) => continue,
+        };
+        for file in files.flatten() {
+            if !config.is_match(&file.file_name().to_string_lossy()) {
+                continue;
+            }
+            if let Ok((stdout, _)) = Command::new(file.path()).arg("--prefix").check_output() {
+                prefixes.push(PathBuf::from(stdout.trim()));
+            }
+        }
+    }
+
+    let mut builds: Vec<(Version, Build)> = Vec::new();
+    for prefix in prefixes {
+        let prefix = prefix.canonicalize().unwrap_or(prefix);
+        if prefix == Path::new("/usr") || builds.iter().any(|(_, b)| b.prefix == prefix) {
+            continue;
+        }
+        let version = match Build::from_path(&prefix).version() {
+            Ok(version) => version,
+            Err(_) => continue,
+        };
+        let build = Build::system_version(version.major, &prefix);
+        if builds.iter().all(|(_, b)| b.name != build.name) {
+            builds.push((version, build));
+        }
+    }
+    builds.sort_by(|a, b| a.0.cmp(&b.0));
+    builds.into_iter().map(|(_, build)| build).collect()
+}
+
+/// Name of the build in a `.llvmenv` file, `None` if it cannot be read
+fn read_env(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Registry of local `.llvmenv` files written by [Build::set_local], in `$XDG_CONFIG_HOME/llvmenv/locals.toml`
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct Locals {
+    #[serde(default)]
+    paths: BTreeSet<PathBuf>,
+}
+
+impl Locals {
+    fn load() -> Result<Self> {
+        let path = config_dir()?.join(LOCALS_TOML);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(&path).with(&path)?)?)
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = config_dir()?.join(LOCALS_TOML);
+        fs::write(&path, toml::to_string_pretty(self)?).with(&path)
+    }
+
+    /// Forget files which have been removed
+    fn prune(&mut self) {
+        self.paths.retain(|path| path.is_file());
+    }
+}
+
+/// Forget local `.llvmenv` files which have been removed from the registry
+pub fn prune_locals() -> Result<()> {
+    let mut locals = Locals::load()?;
+    let len = locals.paths.len();
+    locals.prune();
+    if locals.paths.len() != len {
+        locals.save()?;
+    }
+    Ok(())
+}
+
+/// Why a candidate of the build setting is accepted or rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Accepted,
+    NotFound,
+    Unreadable(String),
+    Empty,
+    /// The named build is not installed
+    Missing(String),
+    /// The name cannot be resolved, e.g. cyclic aliases
+    Invalid(String),
+    /// No build satisfies the version requirement
+    NoMatch(String),
+    /// The build does not provide these tools
+    MissingComponents(Vec<String>),
+    /// The named build runs on another host, given as name and host triple
+    ForeignHost(String, String),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Accepted => write!(f, "accepted"),
+            Verdict::NotFound => write!(f, "not found"),
+            Verdict::Unreadable(e) => write!(f, "rejected, unreadable: {}", e),
+            Verdict::Empty => write!(f, "rejected, empty"),
+            Verdict::Missing(name) => write!(f, "rejected, {} is not installed", name),
+            Verdict::Invalid(e) => write!(f, "rejected, {}", e),
+            Verdict::NoMatch(e) => write!(f, "rejected, {}", e),
+            Verdict::MissingComponents(tools) => {
+                write!(f, "rejected, {} not provided", tools.join(", "))
+            }
+            Verdict::ForeignHost(name, host) => {
+                write!(f, "rejected, {} runs on {}", name, host)
+            }
+        }
+    }
+}
+
+/// Candidate of the build setting examined by [seek_build]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// Path of `.llvmenv`, or `LLVMENV_BUILD`
+    pub source: String,
+    /// Name of the build written in it
+    pub content: Option<String>,
+    pub verdict: Verdict,
+}
+
+/// Examine the build named in `.llvmenv` or `LLVMENV_BUILD`
+fn examine(source: String, content: String, trace: &mut Vec<Candidate>) -> Option<Build> {
+    let content = content.trim().to_string();
+    let (build, verdict) = if content.is_empty() {
+        (None, Verdict::Empty)
     } else {
-        Ok(None)
+        match Selector::parse(&content)
+            .map_err(Verdict::Invalid)
+            .and_then(|s| s.select())
+        {
+            Ok(build) => (Some(build), Verdict::Accepted),
+            Err(verdict) => (None, verdict),
+        }
+    };
+    trace.push(Candidate {
+        source,
+        content: Some(content.lines().collect::<Vec<_>>().join("; ")),
+        verdict,
+    });
+    build
+}
+
+fn load_env_file(env: &Path, trace: &mut Vec<Candidate>) -> Option<Build> {
+    let source = env.display().to_string();
+    if !env.exists() {
+        trace.push(Candidate {
+            source,
+            content: None,
+            verdict: Verdict::NotFound,
+        });
+        return None;
+    }
+    match fs::read_to_string(env) {
+        Ok(content) => {
+            let mut build = examine(source, content, trace)?;
+            build.llvmenv = Some(env.to_owned());
+            Some(build)
+        }
+        Err(e) => {
+            trace.push(Candidate {
+                source,
+                content: None,
+                verdict: Verdict::Unreadable(e.to_string()),
+            });
+            None
+        }
     }
 }
 
-fn load_global_env() -> Result<Option<Build>> {
-    load_local_env(&config_dir()?)
+/// Seek `.llvmenv` or `.llvm-version` from `dir` to the root, and then the global setting in `global_dir`
+fn seek_from(dir: &Path, global_dir: &Path, trace: &mut Vec<Candidate>) -> Build {
+    for path in dir.ancestors() {
+        for file in [LLVMENV_FN, LLVM_VERSION_FN] {
+            if let Some(build) = load_env_file(&path.join(file), trace) {
+                return build;
+            }
+        }
+    }
+    load_env_file(&global_dir.join(LLVMENV_FN), trace).unwrap_or_else(Build::system)
 }
 
-pub fn seek_build() -> Result<Build> {
-    // Seek .llvmenv from $PWD
-    let mut path = env::current_dir().unwrap();
-    loop {
-        if let Some(mut build) = load_local_env(&path)? {
-            build.llvmenv = Some(path.join(LLVMENV_FN));
-            return Ok(build);
-        }
-        path = match path.parent() {
-            Some(path) => path.into(),
-            None => break,
+/// Seek the build, recording every examined candidate into `trace`
+pub fn explain_build(trace: &mut Vec<Candidate>) -> Result<Build> {
+    // LLVMENV_BUILD overrides the settings in files
+    if let Some(name) = env::var(LLVMENV_BUILD).ok().filter(|name| !name.is_empty()) {
+        return match examine(LLVMENV_BUILD.into(), name.clone(), trace) {
+            Some(mut build) => {
+                build.from_env = true;
+                Ok(build)
+            }
+            None => match trace.last().map(|c| &c.verdict) {
+                Some(Verdict::ForeignHost(name, host)) => Err(Error::ForeignHostBuild {
+                    name: name.clone(),
+                    host: host.clone(),
+                }),
+                _ => Err(Error::BuildNotFound {
+                    name,
+                    setting: LLVMENV_BUILD.into(),
+                }),
+            },
         };
     }
-    // check global setting
-    if let Some(mut build) = load_global_env()? {
-        build.llvmenv = Some(config_dir()?.join(LLVMENV_FN));
-        return Ok(build);
+    let dir = env::current_dir().with(".")?;
+    Ok(seek_from(&dir, &config_dir()?, trace))
+}
+
+/// Build selected by `LLVMENV_BUILD`, `.llvmenv` from the current directory, the global setting, or `system`
+///
+/// A `.llvmenv` naming a build which is not installed is handled by the `auto_install` config
+/// (see [install](../install/index.html) module), or skipped with a warning.
+pub fn seek_build() -> Result<Build> {
+    seek(true)
+}
+
+/// Same as [seek_build], but a missing build is only warned and never installed.
+/// Shims use it, since they must not stop the tool for a prompt or a long build.
+pub fn seek_installed_build() -> Result<Build> {
+    seek(false)
+}
+
+fn seek(auto_install: bool) -> Result<Build> {
+    let mut trace = Vec::new();
+    let build = explain_build(&mut trace);
+    if let Some((name, source)) = first_missing(&trace) {
+        // A missing LLVMENV_BUILD is already an error, and is not installed
+        if source == LLVMENV_BUILD {
+            return build;
+        }
+        if auto_install && install::on_missing(name, source)? {
+            return explain_build(&mut Vec::new());
+        }
+        warn!("{} names {}, which is not installed", source, name);
     }
-    Ok(Build::system())
+    build
+}
+
+/// Name of the first missing build in the trace, and where it is named
+pub fn first_missing(trace: &[Candidate]) -> Option<(&str, &str)> {
+    trace.iter().find_map(|c| match &c.verdict {
+        Verdict::Missing(name) => Some((name.as_str(), c.source.as_str())),
+        _ => None,
+    })
 }
 
 pub fn expand(archive: &Path, verbose: bool) -> Result<()> {
@@ -189,6 +701,20 @@ pub fn expand(archive: &Path, verbose: bool) -> Result<()> {
         .arg(archive)
         .current_dir(data_dir()?)
         .check_run()?;
+    // Archives made by `llvmenv archive` contain `{name}/`
+    let name = archive
+        .file_name()
+        .and_then(|name| name.to_str()?.strip_suffix(".tar.xz"));
+    if let Some(prefix) = name
+        .map(|name| data_dir().map(|dir| dir.join(name)))
+        .transpose()?
+    {
+        if prefix.is_dir() {
+            let mut manifest = Manifest::load(&prefix)?.unwrap_or_default();
+            manifest.archive = Some(archive.display().to_string());
+            manifest.save(&prefix)?;
+        }
+    }
     Ok(())
 }
 
@@ -218,4 +744,172 @@ mod tests {
 
         Ok(())
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn side_by_side_system_builds() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let root = tempfile::TempDir::new()?;
+        // Script which prints `output`
+        let script = |path: PathBuf, output: &str| -> io::Result<()> {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, format!("#!/bin/sh\necho {}\n", output))?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+        };
+        let llvm14 = root.path().join("lib/llvm-14");
+        script(root.path().join("lib/llvm-15/bin/llvm-config"), "15.0.7")?;
+        script(llvm14.join("bin/llvm-config"), "14.0.6")?;
+        // llvm-config-14 in PATH points at the same prefix
+        let bin = root.path().join("bin");
+        script(bin.join("llvm-config-14"), &llvm14.display().to_string())?;
+
+        let pattern = root.path().join("lib/llvm-*").display().to_string();
+        let builds = discover_system_builds(&[&pattern], &[bin]);
+        let names: Vec<&str> = builds.iter().map(|b| b.name()).collect();
+        assert_eq!(names, ["system-14", "system-15"]);
+        assert!(builds.iter().all(|b| b.is_system()));
+        Ok(())
+    }
+
+    #[test]
+    fn skip_missing_builds() -> io::Result<()> {
+        // Builds and aliases of the host must not be found
+        let _dirs = isolate_dirs();
+        let root = tempfile::TempDir::new()?;
+        let project = root.path().join("project");
+        let sub = project.join("sub");
+        fs::create_dir_all(&sub)?;
+        fs::write(sub.join(LLVMENV_FN), "\n")?;
+        fs::write(project.join(LLVMENV_FN), "llvmenv-not-installed\n")?;
+        fs::write(project.join(LLVM_VERSION_FN), ">=999\n")?;
+        let global = root.path().join("global");
+
+        let mut trace = Vec::new();
+        seek_from(&sub, &global, &mut trace);
+        // Files in the ancestors of the temporary directory depend on the host
+        let root = root.path().display().to_string();
+        let examined: Vec<&Candidate> = trace
+            .iter()
+            .filter(|c| c.source.starts_with(&root) && c.verdict != Verdict::NotFound)
+            .collect();
+        assert_eq!(examined.len(), 3);
+        assert_eq!(examined[0].verdict, Verdict::Empty);
+        assert_eq!(
+            examined[1].verdict,
+            Verdict::Missing("llvmenv-not-installed".into())
+        );
+        assert_eq!(
+            examined[1].content.as_deref(),
+            Some("llvmenv-not-installed")
+        );
+        // .llvm-version is read after .llvmenv in the same directory
+        assert!(examined[2].source.ends_with(LLVM_VERSION_FN));
+        assert!(matches!(examined[2].verdict, Verdict::NoMatch(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn prune_removed_locals() -> io::Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let kept = dir.path().join(LLVMENV_FN);
+        fs::write(&kept, "13.0.0\n")?;
+        let mut locals = Locals::default();
+        locals.paths.insert(kept.clone());
+        locals
+            .paths
+            .insert(dir.path().join("removed").join(LLVMENV_FN));
+        locals.prune();
+        assert_eq!(
+            locals.paths.into_iter().collect::<Vec<_>>(),
+            vec![kept.clone()]
+        );
+        assert_eq!(read_env(&kept).as_deref(), Some("13.0.0"));
+        Ok(())
+    }
+
+    #[test]
+    fn refuse_uninstall_build_in_use() -> Result<()> {
+        let _dirs = isolate_dirs();
+        let name = "llvmenv-test-in-use";
+        let prefix = data_dir()?.join(name);
+        fs::create_dir_all(prefix.join("bin")).with(&prefix)?;
+        let project = tempfile::TempDir::new().unwrap();
+        let build = Build::from_name(name)?;
+        build.set_local(project.path())?;
+        let env = project.path().join(LLVMENV_FN).canonicalize().unwrap();
+
+        match build.uninstall(false) {
+            Err(Error::BuildInUse { paths, .. }) => assert_eq!(paths, std::slice::from_ref(&env)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(prefix.exists());
+        // The build is not referred any more once the .llvmenv is rewritten
+        fs::write(&env, "system").with(&env)?;
+        assert!(build.references()?.is_empty());
+        fs::remove_dir_all(&prefix).with(&prefix)?;
+        Ok(())
+    }
+
+    #[test]
+    fn references_are_read_only() -> Result<()> {
+        let _dirs = isolate_dirs();
+        let removed = tempfile::TempDir::new().unwrap().path().join(LLVMENV_FN);
+        let mut locals = Locals::load()?;
+        locals.paths.insert(removed.clone());
+        locals.save()?;
+        Build::system().references()?;
+        assert!(Locals::load()?.paths.contains(&removed));
+        prune_locals()?;
+        assert!(!Locals::load()?.paths.contains(&removed));
+        Ok(())
+    }
+
+    #[test]
+    fn reject_foreign_host() -> Result<()> {
+        let prefix = tempfile::TempDir::new().unwrap();
+        let build = Build::from_path(prefix.path());
+        assert!(build.check_host().is_ok());
+        let manifest = Manifest {
+            foreign_host: Some("aarch64-unknown-linux-gnu".into()),
+            ..Default::default()
+        };
+        manifest.save(prefix.path())?;
+        assert!(matches!(
+            build.check_host(),
+            Err(Error::ForeignHostBuild { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn filter_builds_by_entry() {
+        let info = |name: &str, entry: Option<&str>, size: u64| BuildInfo {
+            name: name.into(),
+            prefix: PathBuf::from("/tmp").join(name),
+            version: Version::parse(name).ok(),
+            build_type: None,
+            installed_at: None,
+            size: Some(size),
+            origin: "unknown".into(),
+            entry: entry.map(Into::into),
+        };
+        let infos = vec![
+            info("16.0.6", Some("16.0.6"), 10),
+            info("my-trunk", Some("llvm-mirror"), 30),
+            info("my-trunk-debug", Some("llvm-mirror"), 20),
+            info("system-14", None, 40),
+        ];
+        let names = |infos: Vec<BuildInfo>| -> Vec<String> {
+            infos.into_iter().map(|info| info.name).collect()
+        };
+        assert_eq!(
+            names(arrange_infos(infos.clone(), Some("llvm-mirror"), "name")),
+            ["my-trunk", "my-trunk-debug"]
+        );
+        assert!(arrange_infos(infos.clone(), Some("unknown-entry"), "name").is_empty());
+        assert_eq!(
+            names(arrange_infos(infos, None, "size")),
+            ["system-14", "my-trunk", "my-trunk-debug", "16.0.6"]
+        );
+    }
 }
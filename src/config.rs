@@ -1,12 +1,16 @@
 use log::info;
+use serde_derive::Deserialize;
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
 
 use crate::error::*;
+use crate::install::AutoInstallConfig;
+use crate::jobs::JobsConfig;
 
 pub const APP_NAME: &str = "llvmenv";
 pub const ENTRY_TOML: &str = "entry.toml";
+pub const CONFIG_TOML: &str = "config.toml";
 
 const LLVM_MIRROR: &str = include_str!("llvm.toml");
 
@@ -51,3 +55,39 @@ pub fn init_config() -> Result<()> {
         Err(Error::ConfigureAlreadyExists { path: entry })
     }
 }
+
+/// Global setting of llvmenv in `$XDG_CONFIG_HOME/llvmenv/config.toml`
+///
+/// Every table is optional, and the file itself can be missing.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Config {
+    /// Memory budgets for parallel jobs, see [jobs](../jobs/index.html) module
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    /// How to handle builds named by `.llvmenv` but missing, see [install](../install/index.html) module
+    #[serde(default)]
+    pub auto_install: AutoInstallConfig,
+}
+
+pub fn load_config() -> Result<Config> {
+    let path = config_dir()?.join(CONFIG_TOML);
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    Ok(toml::from_str(&fs::read_to_string(&path).with(&path)?)?)
+}
+
+/// Point the config and data directories at an empty directory shared by the tests in this process,
+/// so that the builds, aliases and links of the host are not seen.
+/// The tests using it are serialized by the returned guard, since they modify the registries.
+#[cfg(test)]
+pub(crate) fn isolate_dirs() -> std::sync::MutexGuard<'static, ()> {
+    static ONCE: std::sync::Once = std::sync::Once::new();
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    ONCE.call_once(|| {
+        let root = tempfile::TempDir::new().unwrap().keep();
+        std::env::set_var("XDG_CONFIG_HOME", root.join("config"));
+        std::env::set_var("XDG_DATA_HOME", root.join("data"));
+    });
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
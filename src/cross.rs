@@ -0,0 +1,164 @@
+//! Cross-compilation entries
+//!
+//! An entry with `host_triple` builds a toolchain which runs on another host:
+//!
+//! ```toml
+//! [17.0.6-aarch64]
+//! url = "https://github.com/llvm/llvm-project/releases/download/llvmorg-17.0.6/llvm-17.0.6.src.tar.xz"
+//! host_triple = "aarch64-linux-gnu"
+//! sysroot = "/usr/aarch64-linux-gnu"
+//! cross_toolchain = "aarch64-linux-gnu-"  # use aarch64-linux-gnu-gcc and aarch64-linux-gnu-g++
+//! ```
+//!
+//! `cross_toolchain` is the prefix of GNU cross compilers. Without it, clang (of `host_compiler` if set)
+//! compiles for `host_triple` by `--target`.
+//!
+//! llvmenv generates a cmake toolchain file `llvmenv-toolchain.cmake` in the build dir,
+//! and first builds `llvm-tblgen` and `clang-tblgen` for this machine in `build-native`,
+//! since tablegen has to run while building.
+//! The result is recorded as a foreign-host build, which cannot be activated by `llvmenv global` or `llvmenv local`.
+
+use std::path::{Path, PathBuf};
+
+/// Name of the cmake toolchain file generated in the build dir
+pub const TOOLCHAIN_FILE: &str = "llvmenv-toolchain.cmake";
+
+/// Compilers and sysroot to build for another host
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toolchain {
+    /// Triple of the host where the toolchain runs, e.g. "aarch64-linux-gnu"
+    pub triple: String,
+    pub sysroot: Option<PathBuf>,
+    pub c_compiler: String,
+    pub cxx_compiler: String,
+    /// Whether the compilers are clang, which needs `--target`
+    pub clang: bool,
+}
+
+impl Toolchain {
+    /// `cross_toolchain` is the prefix of GNU cross compilers, and
+    /// `clang_bin` is the directory of clang used without it (`None` means clang in `PATH`).
+    ///
+    /// ```
+    /// use llvmenv::cross::Toolchain;
+    /// let gnu = Toolchain::new("aarch64-linux-gnu", None, Some("aarch64-linux-gnu-"), None);
+    /// assert_eq!(gnu.c_compiler, "aarch64-linux-gnu-gcc");
+    /// assert_eq!(gnu.cxx_compiler, "aarch64-linux-gnu-g++");
+    /// let clang = Toolchain::new("riscv64-linux-gnu", None, None, Some("/opt/llvm/bin".as_ref()));
+    /// assert_eq!(clang.cxx_compiler, "/opt/llvm/bin/clang++");
+    /// assert!(clang.clang);
+    /// ```
+    pub fn new(
+        triple: &str,
+        sysroot: Option<&Path>,
+        cross_toolchain: Option<&str>,
+        clang_bin: Option<&Path>,
+    ) -> Self {
+        let (c_compiler, cxx_compiler, clang) = match (cross_toolchain, clang_bin) {
+            (Some(prefix), _) => (format!("{}gcc", prefix), format!("{}g++", prefix), false),
+            (None, Some(bin)) => (
+                bin.join("clang").display().to_string(),
+                bin.join("clang++").display().to_string(),
+                true,
+            ),
+            (None, None) => ("clang".into(), "clang++".into(), true),
+        };
+        Toolchain {
+            triple: triple.into(),
+            sysroot: sysroot.map(|p| p.to_owned()),
+            c_compiler,
+            cxx_compiler,
+            clang,
+        }
+    }
+
+    /// `CMAKE_SYSTEM_NAME` of the host
+    pub fn system_name(&self) -> &str {
+        system_name(&self.triple)
+    }
+
+    /// `CMAKE_SYSTEM_PROCESSOR`, the architecture part of the triple
+    pub fn processor(&self) -> &str {
+        self.triple.split('-').next().unwrap()
+    }
+
+    /// Content of the cmake toolchain file
+    pub fn cmake(&self) -> String {
+        let mut lines = vec![
+            "# Generated by llvmenv".to_string(),
+            format!("set(CMAKE_SYSTEM_NAME {})", self.system_name()),
+            format!("set(CMAKE_SYSTEM_PROCESSOR {})", self.processor()),
+        ];
+        if let Some(sysroot) = &self.sysroot {
+            lines.push(format!("set(CMAKE_SYSROOT \"{}\")", sysroot.display()));
+        }
+        lines.push(format!("set(CMAKE_C_COMPILER \"{}\")", self.c_compiler));
+        lines.push(format!("set(CMAKE_CXX_COMPILER \"{}\")", self.cxx_compiler));
+        if self.clang {
+            lines.push(format!("set(CMAKE_C_COMPILER_TARGET {})", self.triple));
+            lines.push(format!("set(CMAKE_CXX_COMPILER_TARGET {})", self.triple));
+        }
+        // Programs run while building are of this machine, and others are of the host
+        lines.push("set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)".into());
+        for kind in ["LIBRARY", "INCLUDE", "PACKAGE"] {
+            lines.push(format!("set(CMAKE_FIND_ROOT_PATH_MODE_{} ONLY)", kind));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// `CMAKE_SYSTEM_NAME` corresponding to the OS part of the triple
+///
+/// ```
+/// use llvmenv::cross::system_name;
+/// assert_eq!(system_name("aarch64-linux-gnu"), "Linux");
+/// assert_eq!(system_name("riscv64-unknown-linux-gnu"), "Linux");
+/// assert_eq!(system_name("arm64-apple-darwin"), "Darwin");
+/// assert_eq!(system_name("x86_64-pc-windows-msvc"), "Windows");
+/// assert_eq!(system_name("arm-none-eabi"), "Generic");
+/// ```
+pub fn system_name(triple: &str) -> &'static str {
+    let parts: Vec<&str> = triple.split('-').skip(1).collect();
+    let has = |os: &str| parts.iter().any(|p| p.starts_with(os));
+    // Android triples also contain "linux", e.g. aarch64-linux-android
+    if has("android") {
+        "Android"
+    } else if has("linux") {
+        "Linux"
+    } else if has("darwin") || has("macos") {
+        "Darwin"
+    } else if has("windows") || has("mingw") {
+        "Windows"
+    } else if has("freebsd") {
+        "FreeBSD"
+    } else {
+        "Generic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toolchain_file() {
+        let toolchain = Toolchain::new(
+            "aarch64-linux-gnu",
+            Some(Path::new("/usr/aarch64-linux-gnu")),
+            None,
+            None,
+        );
+        let cmake = toolchain.cmake();
+        assert!(cmake.contains("set(CMAKE_SYSTEM_NAME Linux)\n"));
+        assert!(cmake.contains("set(CMAKE_SYSTEM_PROCESSOR aarch64)\n"));
+        assert!(cmake.contains("set(CMAKE_SYSROOT \"/usr/aarch64-linux-gnu\")\n"));
+        assert!(cmake.contains("set(CMAKE_CXX_COMPILER_TARGET aarch64-linux-gnu)\n"));
+    }
+
+    #[test]
+    fn android_is_not_linux() {
+        assert_eq!(system_name("aarch64-linux-android"), "Android");
+        assert_eq!(system_name("armv7a-linux-androideabi"), "Android");
+        assert_eq!(system_name("aarch64-unknown-linux-gnu"), "Linux");
+    }
+}
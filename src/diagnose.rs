@@ -0,0 +1,204 @@
+//! Diagnose build failures from the captured output
+//!
+//! When an external command fails, its output is scanned for known failure signatures,
+//! e.g. a compiler killed by the OOM killer or a too old cmake.
+//! Each found signature becomes a [Hint][Hint] attached to `Error::CommandError`.
+//!
+//! [Hint]: ./struct.Hint.html
+
+use regex::Regex;
+use std::fmt;
+
+/// Known causes of build failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// Compiler process killed by the OOM killer
+    CompilerOutOfMemory,
+    /// Linker ran out of memory
+    LinkerOutOfMemory,
+    /// cmake is older than the LLVM source requires
+    CMakeTooOld,
+    /// zlib development files are not installed
+    MissingZlib,
+    /// Python interpreter is not found
+    MissingPython,
+    /// Host C/C++ compiler is too old for this LLVM version
+    UnsupportedHostCompiler,
+}
+
+/// Known failure found in the output of a command, with a suggestion to fix it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hint {
+    pub cause: Cause,
+    /// The line of the output which matched the failure signature
+    pub evidence: String,
+    pub suggestion: String,
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (found: {})", self.suggestion, self.evidence.trim())
+    }
+}
+
+struct Signature {
+    cause: Cause,
+    pattern: Regex,
+    /// Suggestion. `{1}`, `{2}`, ... are replaced by the captures of the pattern.
+    suggestion: &'static str,
+}
+
+lazy_static::lazy_static! {
+    static ref SIGNATURES: Vec<Signature> = vec![
+        // Linker signatures first, since they also contain "signal 9" or "Killed"
+        Signature {
+            cause: Cause::LinkerOutOfMemory,
+            pattern: Regex::new(
+                r"(collect2: fatal error: ld terminated with signal 9|ld(\.gold|\.lld|\.bfd)?: .*(memory exhausted|out of memory|cannot allocate memory)|ld returned 137)"
+            ).unwrap(),
+            suggestion: "The linker ran out of memory. Limit parallel links with the Ninja generator (-G Ninja) by raising `link_memory` in the [jobs] table of config.toml or adding `LLVM_PARALLEL_LINK_JOBS = \"1\"` to `option` of the entry, or link with lld (`LLVM_USE_LINKER = \"lld\"`)",
+        },
+        Signature {
+            cause: Cause::CompilerOutOfMemory,
+            pattern: Regex::new(
+                r"(Killed signal terminated program|internal compiler error: Killed|virtual memory exhausted|unable to execute command: Killed|terminated by signal 9|c\+\+: fatal error: Killed)"
+            ).unwrap(),
+            suggestion: "The compiler was killed, most likely by the OOM killer. Lower the number of parallel jobs, e.g. `llvmenv build-entry -j 2`, or raise `compile_memory` in the [jobs] table of config.toml",
+        },
+        Signature {
+            cause: Cause::CMakeTooOld,
+            pattern: Regex::new(
+                r"CMake ([0-9.]+) or higher is required\.\s+You are running version ([0-9.]+)"
+            ).unwrap(),
+            suggestion: "This LLVM requires CMake {1} or later, but {2} is installed. Install a newer cmake, e.g. `pip install cmake`",
+        },
+        Signature {
+            cause: Cause::MissingZlib,
+            pattern: Regex::new(r"(Could NOT find ZLIB|zlib\.h: No such file or directory)").unwrap(),
+            suggestion: "zlib is not found. Install its development package (zlib1g-dev on Debian/Ubuntu, zlib-devel on Fedora), or add `LLVM_ENABLE_ZLIB = \"OFF\"` to `option` of the entry",
+        },
+        Signature {
+            cause: Cause::MissingPython,
+            pattern: Regex::new(
+                r"(Could NOT find Python3|Unable to find Python interpreter|Could NOT find PythonInterp|python3?: (command )?not found)"
+            ).unwrap(),
+            suggestion: "Python is not found. Install python3, or point cmake to it by adding `Python3_EXECUTABLE` to `option` of the entry",
+        },
+        Signature {
+            cause: Cause::UnsupportedHostCompiler,
+            pattern: Regex::new(r"Host (GCC|Clang|Visual Studio) version must be at least ([0-9.]+)").unwrap(),
+            suggestion: "The host {1} is too old to build this LLVM, which requires {1} {2} or later. Install a newer compiler and select it with the CC/CXX environment variables",
+        },
+    ];
+}
+
+/// Scan the output of a failed command for known failure signatures
+///
+/// Each cause is reported at most once, with the first matched line.
+///
+/// ```
+/// use llvmenv::diagnose::{diagnose, Cause};
+/// let output = "c++: fatal error: Killed signal terminated program cc1plus\ncompilation terminated.";
+/// let hints = diagnose(output);
+/// assert_eq!(hints.len(), 1);
+/// assert_eq!(hints[0].cause, Cause::CompilerOutOfMemory);
+/// ```
+pub fn diagnose(output: &str) -> Vec<Hint> {
+    let mut hints: Vec<Hint> = Vec::new();
+    for line in output.lines() {
+        for sig in SIGNATURES.iter() {
+            if hints.iter().any(|hint| hint.cause == sig.cause) {
+                continue;
+            }
+            if let Some(cap) = sig.pattern.captures(line) {
+                let mut suggestion = sig.suggestion.to_string();
+                for i in 1..cap.len() {
+                    let value = cap.get(i).map(|m| m.as_str()).unwrap_or("");
+                    suggestion = suggestion.replace(&format!("{{{}}}", i), value);
+                }
+                hints.push(Hint {
+                    cause: sig.cause,
+                    evidence: line.into(),
+                    suggestion,
+                });
+                // One line is regarded as a evidence of one cause
+                break;
+            }
+        }
+    }
+    hints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn causes(output: &str) -> Vec<Cause> {
+        diagnose(output)
+            .into_iter()
+            .map(|hint| hint.cause)
+            .collect()
+    }
+
+    #[test]
+    fn compiler_oom() {
+        assert_eq!(
+            causes("g++: internal compiler error: Killed (program cc1plus)"),
+            vec![Cause::CompilerOutOfMemory]
+        );
+        assert_eq!(
+            causes("clang-17: error: unable to execute command: Killed"),
+            vec![Cause::CompilerOutOfMemory]
+        );
+    }
+
+    #[test]
+    fn linker_oom() {
+        assert_eq!(
+            causes("collect2: fatal error: ld terminated with signal 9 [Killed]"),
+            vec![Cause::LinkerOutOfMemory]
+        );
+        assert_eq!(
+            causes("/usr/bin/ld: failed to set dynamic section sizes: memory exhausted"),
+            vec![Cause::LinkerOutOfMemory]
+        );
+    }
+
+    #[test]
+    fn cmake_too_old() {
+        let hints = diagnose(
+            "CMake Error at CMakeLists.txt:3 (cmake_minimum_required):\n  CMake 3.20.0 or higher is required.  You are running version 3.16.3",
+        );
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].cause, Cause::CMakeTooOld);
+        assert!(hints[0].suggestion.contains("CMake 3.20.0 or later"));
+        assert!(hints[0].suggestion.contains("3.16.3 is installed"));
+    }
+
+    #[test]
+    fn missing_packages() {
+        let output = "-- Could NOT find ZLIB (missing: ZLIB_LIBRARY ZLIB_INCLUDE_DIR)\n\
+                      CMake Error: Could NOT find Python3 (missing: Python3_EXECUTABLE Interpreter)";
+        assert_eq!(
+            causes(output),
+            vec![Cause::MissingZlib, Cause::MissingPython]
+        );
+    }
+
+    #[test]
+    fn host_compiler() {
+        let hints =
+            diagnose("CMake Error: Host GCC version must be at least 7.4, your version is 5.4.0.");
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].cause, Cause::UnsupportedHostCompiler);
+        assert!(hints[0].suggestion.contains("GCC 7.4 or later"));
+    }
+
+    #[test]
+    fn each_cause_once() {
+        let output = "c++: fatal error: Killed signal terminated program cc1plus\n\
+                      c++: fatal error: Killed signal terminated program cc1plus";
+        assert_eq!(causes(output), vec![Cause::CompilerOutOfMemory]);
+        assert!(diagnose("[100/3000] Building CXX object foo.o").is_empty());
+    }
+}
@@ -0,0 +1,467 @@
+//! Check the build environment before building an entry
+//!
+//! `llvmenv build-entry` runs these checks before downloading anything,
+//! so that a missing cmake or compiler is reported before a long download.
+//! They are also available as `llvmenv doctor [entry]`.
+//!
+//! - cmake exists and is new enough for the LLVM version
+//! - the generator program (make or ninja) exists
+//! - host C/C++ compilers exist and are new enough for the LLVM version,
+//!   and also the native compilers for tablegen of cross builds
+//! - python3 exists
+//! - git or svn exists if sources are fetched with them
+//! - free disk space in `cache_dir` and `data_dir` is enough for the build type
+
+use regex::Regex;
+use semver::Version;
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{build::Build, entry::*, error::*, resource::*};
+
+/// Result of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    /// The build may fail or be slow
+    Warning,
+    /// The build will fail
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// What is checked, e.g. "cmake"
+    pub name: String,
+    pub status: Status,
+    pub message: String,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, message: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mark = match self.status {
+            Status::Ok => " ok ",
+            Status::Warning => "warn",
+            Status::Error => "FAIL",
+        };
+        write!(f, "[{}] {}: {}", mark, self.name, self.message)
+    }
+}
+
+/// Minimum versions of build tools required by LLVM
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requirement {
+    pub cmake: Version,
+    pub gcc: Version,
+    pub clang: Version,
+    /// Whether python3 is required to configure
+    pub python3: bool,
+}
+
+impl Requirement {
+    /// Requirements of the LLVM version from "Getting Started with the LLVM System".
+    /// Unknown version, e.g. a git trunk, is regarded as the latest.
+    ///
+    /// ```
+    /// use llvmenv::doctor::Requirement;
+    /// use semver::Version;
+    /// let req = Requirement::for_llvm(Some(&Version::new(17, 0, 6)));
+    /// assert_eq!(req.cmake, Version::new(3, 20, 0));
+    /// assert_eq!(req.gcc, Version::new(7, 4, 0));
+    /// let req = Requirement::for_llvm(Some(&Version::new(10, 0, 0)));
+    /// assert_eq!(req.cmake, Version::new(3, 13, 4));
+    /// assert_eq!(Requirement::for_llvm(None), Requirement::for_llvm(Some(&Version::new(99, 0, 0))));
+    /// ```
+    pub fn for_llvm(version: Option<&Version>) -> Self {
+        let major = version.map(|v| v.major).unwrap_or(u64::MAX);
+        let (cmake, gcc, clang) = if major >= 16 {
+            ((3, 20, 0), (7, 4, 0), (5, 0, 0))
+        } else if major >= 10 {
+            ((3, 13, 4), (5, 1, 0), (3, 5, 0))
+        } else if major >= 7 {
+            ((3, 4, 3), (4, 8, 0), (3, 1, 0))
+        } else {
+            ((2, 8, 12), (4, 8, 0), (3, 1, 0))
+        };
+        let v = |(major, minor, patch)| Version::new(major, minor, patch);
+        Requirement {
+            cmake: v(cmake),
+            gcc: v(gcc),
+            clang: v(clang),
+            python3: major >= 12,
+        }
+    }
+}
+
+/// Disk space in GiB used by a build of each build type: (build tree, install prefix)
+fn disk_usage(build_type: BuildType) -> (u64, u64) {
+    match build_type {
+        BuildType::Debug => (70, 25),
+        BuildType::RelWithDebInfo => (45, 15),
+        BuildType::Release => (15, 5),
+        BuildType::MinSizeRel => (12, 4),
+    }
+}
+
+/// Source tree of a remote entry in GiB
+const SOURCE_SIZE: u64 = 3;
+
+/// Checks which do not depend on the source of an entry
+pub fn check_host(version: Option<&Version>, generator: &CMakeGenerator) -> Vec<Check> {
+    check_tools(version, generator, &native_compilers(None))
+}
+
+/// C/C++ compilers for this machine: clang of the host compiler build, or `CC` and `CXX`
+fn native_compilers(host: Option<&Build>) -> [(&'static str, String); 2] {
+    match host {
+        Some(build) => {
+            let bin = build.prefix().join("bin");
+            [("CC", "clang"), ("CXX", "clang++")]
+                .map(|(var, tool)| (var, bin.join(tool).display().to_string()))
+        }
+        None => [("CC", "cc"), ("CXX", "c++")]
+            .map(|(var, default)| (var, env::var(var).unwrap_or_else(|_| default.into()))),
+    }
+}
+
+fn check_tools(
+    version: Option<&Version>,
+    generator: &CMakeGenerator,
+    compilers: &[(&str, String)],
+) -> Vec<Check> {
+    let req = Requirement::for_llvm(version);
+    let mut checks = vec![check_cmake(&req)];
+    checks.extend(check_generator(generator));
+    for (var, compiler) in compilers {
+        checks.push(check_compiler(var, compiler, &req));
+    }
+    checks.push(check_python(&req));
+    checks
+}
+
+/// All checks for building the entry
+///
+/// If the entry has `host_compiler` or is cross-compiled, its compilers are checked instead of `CC` and `CXX`.
+pub fn preflight(entry: &Entry) -> Result<Vec<Check>> {
+    let mut checks = match (entry.toolchain()?, entry.host_compiler()?) {
+        (Some(toolchain), host) => {
+            let compilers = [
+                ("CC", toolchain.c_compiler),
+                ("CXX", toolchain.cxx_compiler),
+            ];
+            let mut checks = check_tools(entry.version(), entry.generator(), &compilers);
+            // The native stage for tablegen is compiled by the compilers of this machine
+            let req = Requirement::for_llvm(entry.version());
+            for (var, compiler) in native_compilers(host.as_ref()) {
+                checks.push(check_compiler(&format!("native {}", var), &compiler, &req));
+            }
+            if let Some(sysroot) = toolchain.sysroot {
+                checks.push(if sysroot.is_dir() {
+                    Check::new("sysroot", Status::Ok, sysroot.display().to_string())
+                } else {
+                    Check::new(
+                        "sysroot",
+                        Status::Error,
+                        format!("{} does not exist", sysroot.display()),
+                    )
+                });
+            }
+            checks
+        }
+        (None, Some(build)) => check_tools(
+            entry.version(),
+            entry.generator(),
+            &native_compilers(Some(&build)),
+        ),
+        (None, None) => check_host(entry.version(), entry.generator()),
+    };
+    let fetch = !entry.source_root()?.exists();
+    checks.extend(check_vcs(entry, fetch)?);
+    checks.extend(check_disk(entry, fetch)?);
+    Ok(checks)
+}
+
+/// Output of `{program} --version`, or `None` if not found
+fn version_output(program: &str) -> Option<String> {
+    let (stdout, stderr) = Command::new(program).arg("--version").check_output().ok()?;
+    Some(format!("{}{}", stdout, stderr))
+}
+
+/// Parse first `x.y.z` or `x.y` in the text
+fn parse_version(text: &str) -> Option<Version> {
+    let cap = Regex::new(r"(\d+)\.(\d+)(\.(\d+))?")
+        .unwrap()
+        .captures(text)?;
+    Some(Version::new(
+        cap[1].parse().ok()?,
+        cap[2].parse().ok()?,
+        cap.get(4).map_or(Some(0), |m| m.as_str().parse().ok())?,
+    ))
+}
+
+fn check_version(name: &str, found: Option<Version>, required: &Version) -> Check {
+    match found {
+        Some(found) if &found >= required => Check::new(
+            name,
+            Status::Ok,
+            format!("{} (>= {} required)", found, required),
+        ),
+        Some(found) => Check::new(
+            name,
+            Status::Error,
+            format!("{} is too old, {} or later is required", found, required),
+        ),
+        None => Check::new(name, Status::Warning, "Cannot detect version"),
+    }
+}
+
+fn check_cmake(req: &Requirement) -> Check {
+    match version_output("cmake") {
+        Some(out) => check_version("cmake", parse_version(&out), &req.cmake),
+        None => Check::new(
+            "cmake",
+            Status::Error,
+            format!("Not found. cmake {} or later is required", req.cmake),
+        ),
+    }
+}
+
+fn check_generator(generator: &CMakeGenerator) -> Option<Check> {
+    let program = match generator {
+        CMakeGenerator::Ninja => "ninja",
+        CMakeGenerator::Makefile => "make",
+        // cmake uses Unix Makefiles by default on Unix
+        CMakeGenerator::Platform if cfg!(unix) => "make",
+        _ => return None,
+    };
+    Some(match which::which(program) {
+        Ok(path) => Check::new(program, Status::Ok, path.display().to_string()),
+        Err(_) => Check::new(
+            program,
+            Status::Error,
+            format!("Not found, but required by the {:?} generator", generator),
+        ),
+    })
+}
+
+fn check_compiler(var: &str, compiler: &str, req: &Requirement) -> Check {
+    let name = format!("{} ({})", var, compiler);
+    let out = match version_output(compiler) {
+        Some(out) => out,
+        None => return Check::new(&name, Status::Error, "Not found"),
+    };
+    let first = out.lines().next().unwrap_or("");
+    let found = parse_version(first);
+    if first.contains("clang") {
+        // Apple clang has its own version numbers
+        if first.contains("Apple") {
+            return Check::new(&name, Status::Ok, first.trim());
+        }
+        check_version(&name, found, &req.clang)
+    } else if out.contains("Free Software Foundation")
+        || first.contains("gcc")
+        || first.contains("g++")
+    {
+        check_version(&name, found, &req.gcc)
+    } else {
+        Check::new(
+            &name,
+            Status::Warning,
+            format!("Unknown compiler: {}", first.trim()),
+        )
+    }
+}
+
+fn check_python(req: &Requirement) -> Check {
+    match version_output("python3") {
+        Some(out) => Check::new("python3", Status::Ok, out.trim()),
+        None if req.python3 => Check::new(
+            "python3",
+            Status::Error,
+            "Not found, but required to configure this LLVM",
+        ),
+        None => Check::new(
+            "python3",
+            Status::Warning,
+            "Not found, some tools and tests are disabled",
+        ),
+    }
+}
+
+fn check_vcs(entry: &Entry, fetch: bool) -> Result<Vec<Check>> {
+    let mut programs = Vec::new();
+    for res in entry.resources()? {
+        let program = match Resource::guess_from_url(&res.url)? {
+            Some(Resource::Tar { .. }) => continue,
+            Some(Resource::Svn { .. }) => "svn",
+            // git is used to detect the kind of unknown URLs
+            Some(Resource::Git { .. }) | None => "git",
+        };
+        if !programs.contains(&program) {
+            programs.push(program);
+        }
+    }
+    Ok(programs
+        .into_iter()
+        .map(|program| match which::which(program) {
+            Ok(path) => Check::new(program, Status::Ok, path.display().to_string()),
+            // `--update` also requires it
+            Err(_) if !fetch => Check::new(
+                program,
+                Status::Warning,
+                "Not found. Sources are already fetched, but --update will fail",
+            ),
+            Err(_) => Check::new(
+                program,
+                Status::Error,
+                "Not found, but required to fetch sources",
+            ),
+        })
+        .collect())
+}
+
+/// Available space and mount point of the filesystem containing `path`, using `df`
+fn free_space(path: &Path) -> Option<(u64, String)> {
+    // df requires an existing path
+    let mut path = path.to_owned();
+    while !path.exists() {
+        path = path.parent()?.to_owned();
+    }
+    let (stdout, _) = Command::new("df")
+        .arg("-Pk")
+        .arg(&path)
+        .check_output()
+        .ok()?;
+    let line = stdout.lines().nth(1)?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let kib: u64 = fields.get(3)?.parse().ok()?;
+    let mount = fields.get(5..)?.join(" ");
+    Some((kib / 1024 / 1024, mount))
+}
+
+fn check_disk(entry: &Entry, fetch: bool) -> Result<Vec<Check>> {
+    let (mut build, install) = disk_usage(entry.build_type());
+    // Intermediate bootstrap stages are built next to the final build dir
+    for stage in entry.stages()?.iter().filter(|stage| !stage.install) {
+        build += disk_usage(stage.build_type).0;
+    }
+    let build = if fetch && matches!(entry, Entry::Remote { .. }) {
+        build + SOURCE_SIZE
+    } else {
+        build
+    };
+    let build_dir: PathBuf = entry.build_path()?;
+    let prefix = entry.prefix()?;
+    let targets = [
+        ("build dir", build_dir, build),
+        ("data dir", prefix, install),
+    ];
+
+    // Sum up the requirements of directories on the same filesystem
+    let mut required: Vec<(String, u64, u64, Vec<String>)> = Vec::new();
+    let mut checks = Vec::new();
+    for (name, path, size) in targets {
+        match free_space(&path) {
+            Some((free, mount)) => match required.iter_mut().find(|(m, ..)| m == &mount) {
+                Some((_, _, total, names)) => {
+                    *total += size;
+                    names.push(format!("{} {}", name, path.display()));
+                }
+                None => required.push((
+                    mount,
+                    free,
+                    size,
+                    vec![format!("{} {}", name, path.display())],
+                )),
+            },
+            None => checks.push(Check::new(
+                "disk",
+                Status::Warning,
+                format!("Cannot get free space of {}", path.display()),
+            )),
+        }
+    }
+    for (mount, free, total, names) in required {
+        let message = format!(
+            "{} GiB free on {}, about {} GiB required for a {:?} build ({})",
+            free,
+            mount,
+            total,
+            entry.build_type(),
+            names.join(", ")
+        );
+        let status = if free >= total {
+            Status::Ok
+        } else {
+            Status::Error
+        };
+        checks.push(Check::new("disk", status, message));
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiler_version() {
+        assert_eq!(
+            parse_version("g++ (Ubuntu 11.4.0-1ubuntu1~22.04) 11.4.0"),
+            Some(Version::new(11, 4, 0))
+        );
+        assert_eq!(
+            parse_version("cmake version 3.28"),
+            Some(Version::new(3, 28, 0))
+        );
+        assert_eq!(parse_version("no version"), None);
+    }
+
+    #[test]
+    fn native_compilers_of_cross_build() -> Result<()> {
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm-project".into()),
+            host_triple: Some("aarch64-linux-gnu".into()),
+            cross_toolchain: Some("aarch64-linux-gnu-".into()),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("cross", None, setting)?;
+        let names: Vec<String> = preflight(&entry)?.into_iter().map(|c| c.name).collect();
+        assert!(names
+            .iter()
+            .any(|name| name.starts_with("CC (aarch64-linux-gnu-")));
+        assert!(names.iter().any(|name| name.starts_with("native CC (")));
+        assert!(names.iter().any(|name| name.starts_with("native CXX (")));
+        Ok(())
+    }
+
+    #[test]
+    fn version_check() {
+        let required = Version::new(3, 20, 0);
+        assert_eq!(
+            check_version("cmake", Some(Version::new(3, 16, 3)), &required).status,
+            Status::Error
+        );
+        assert_eq!(
+            check_version("cmake", Some(Version::new(3, 28, 3)), &required).status,
+            Status::Ok
+        );
+        assert_eq!(
+            check_version("cmake", None, &required).status,
+            Status::Warning
+        );
+    }
+}
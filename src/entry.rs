@@ -43,6 +43,12 @@
 //! Entry is regarded as *local* if there is `path` property, and *remote* if there is `url` property.
 //! Other options are common to *remote* entries.
 //!
+//! Bootstrap builds
+//! ----------------
+//!
+//! `stages = 2` rebuilds LLVM with the clang built in the first stage.
+//! See [stage](../stage/index.html) module for detail.
+//!
 //! Pre-defined entries
 //! ------------------
 //!
@@ -70,9 +76,27 @@ use log::{info, warn};
 use regex::Regex;
 use semver::Version;
 use serde_derive::Deserialize;
-use std::{collections::HashMap, fs, path::PathBuf, process, str::FromStr};
-
-use crate::{config::*, error::*, resource::*};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
+
+use crate::{
+    build::Build,
+    config::*,
+    cross::*,
+    error::*,
+    jobs::Parallelism,
+    logs::BuildLog,
+    manifest::{Manifest, INCOMPLETE_MARKER},
+    plan::*,
+    resource::*,
+    stage::*,
+};
 
 /// Option for CMake Generators
 ///
@@ -251,6 +275,33 @@ pub struct EntrySetting {
     /// Wether or not this is an individual tarball or a whole project
     #[serde(default)]
     pub project: bool,
+
+    /// Number of bootstrap stages, see [stage](../stage/index.html) module
+    pub stages: Option<u32>,
+
+    /// Setting of the intermediate bootstrap stages
+    pub bootstrap: Option<Bootstrap>,
+
+    /// Build an optimized toolchain by PGO and/or ThinLTO
+    pub optimize: Option<Optimize>,
+
+    /// Shell commands of the PGO training workload. Empty means the default workload.
+    #[serde(default)]
+    pub training: Vec<String>,
+
+    /// Name of the build, e.g. "17.0.6" or "system", whose clang compiles this entry.
+    /// `None` means `cc` and `c++` found by cmake.
+    pub host_compiler: Option<String>,
+
+    /// Triple of the host where the toolchain runs, for cross-compilation.
+    /// See [cross](../cross/index.html) module.
+    pub host_triple: Option<String>,
+
+    /// Sysroot of the host for cross-compilation
+    pub sysroot: Option<String>,
+
+    /// Prefix of GNU cross compilers, e.g. "aarch64-linux-gnu-"
+    pub cross_toolchain: Option<String>,
 }
 
 /// Describes how to compile LLVM/Clang
@@ -326,9 +377,14 @@ pub fn official_releases() -> Vec<Entry> {
         .collect()
 }
 
-pub fn load_entries() -> Result<Vec<Entry>> {
+/// Entries in `entry.toml`, without the official releases
+pub fn load_local_entries() -> Result<Vec<Entry>> {
     let global_toml = config_dir()?.join(ENTRY_TOML);
-    let mut entries = load_entry_toml(&fs::read_to_string(&global_toml).with(&global_toml)?)?;
+    load_entry_toml(&fs::read_to_string(&global_toml).with(&global_toml)?)
+}
+
+pub fn load_entries() -> Result<Vec<Entry>> {
+    let mut entries = load_local_entries()?;
     let mut official = official_releases();
     entries.append(&mut official);
     Ok(entries)
@@ -347,6 +403,9 @@ pub fn load_entry(name: &str) -> Result<Entry> {
     })
 }
 
+/// File in the build dir which stores [Entry::configure_hash](./enum.Entry.html#method.configure_hash)
+const CONFIGURE_HASH: &str = ".llvmenv-configure-hash";
+
 lazy_static::lazy_static! {
     static ref LLVM_9_0_0: Version = Version::new(9, 0, 0);
     static ref LLVM_8_0_1: Version = Version::new(8, 0, 1);
@@ -453,13 +512,50 @@ impl Entry {
         Entry::parse_setting(&name, Some(version), setting).unwrap()
     }
 
-    fn parse_setting(name: &str, version: Option<Version>, setting: EntrySetting) -> Result<Self> {
+    pub(crate) fn parse_setting(
+        name: &str,
+        version: Option<Version>,
+        setting: EntrySetting,
+    ) -> Result<Self> {
         if setting.path.is_some() && setting.url.is_some() {
             return Err(Error::InvalidEntry {
                 name: name.into(),
                 message: "One of Path or URL are allowed".into(),
             });
         }
+        match (setting.stages, &setting.bootstrap) {
+            (Some(0), _) | (_, Some(Bootstrap { stages: 0, .. })) => {
+                return Err(Error::InvalidEntry {
+                    name: name.into(),
+                    message: "stages must be 1 or more".into(),
+                });
+            }
+            (Some(stages), Some(bootstrap)) if stages != bootstrap.stages => {
+                return Err(Error::InvalidEntry {
+                    name: name.into(),
+                    message: "stages and bootstrap.stages are inconsistent".into(),
+                });
+            }
+            _ => {}
+        }
+        if setting.host_triple.is_none()
+            && (setting.sysroot.is_some() || setting.cross_toolchain.is_some())
+        {
+            return Err(Error::InvalidEntry {
+                name: name.into(),
+                message: "sysroot and cross_toolchain require host_triple".into(),
+            });
+        }
+        if setting.host_triple.is_some()
+            && (setting.stages.is_some()
+                || setting.bootstrap.is_some()
+                || setting.optimize.is_some())
+        {
+            return Err(Error::InvalidEntry {
+                name: name.into(),
+                message: "Cross-compilation cannot be combined with bootstrap or optimize".into(),
+            });
+        }
         if let Some(path) = &setting.path {
             if !setting.tools.is_empty() {
                 warn!("'tools' must be used with URL, ignored");
@@ -493,6 +589,14 @@ impl Entry {
         }
     }
 
+    pub fn build_type(&self) -> BuildType {
+        self.setting().build_type
+    }
+
+    pub fn generator(&self) -> &CMakeGenerator {
+        &self.setting().generator
+    }
+
     fn setting_mut(&mut self) -> &mut EntrySetting {
         match self {
             Entry::Remote { setting, .. } => setting,
@@ -511,15 +615,17 @@ impl Entry {
         Ok(())
     }
 
-    pub fn checkout(&self) -> Result<()> {
+    /// Download the sources. Output of git/svn is also written into `log` if given.
+    pub fn checkout(&self, log: Option<&BuildLog>) -> Result<()> {
+        let log = log.map(|log| log.phase("checkout"));
         match self {
             Entry::Remote { url, tools, .. } => {
                 let src = Resource::from_url(url)?;
-                src.download(&self.src_dir()?)?;
+                src.download(&self.src_dir()?, log.as_deref())?;
                 for tool in tools {
                     let path = self.src_dir()?.join(tool.rel_path());
                     let src = Resource::from_url(&tool.url)?;
-                    src.download(&path)?;
+                    src.download(&path, log.as_deref())?;
                 }
             }
             Entry::Local { .. } => {}
@@ -534,14 +640,15 @@ impl Entry {
         Ok(())
     }
 
-    pub fn update(&self) -> Result<()> {
+    pub fn update(&self, log: Option<&BuildLog>) -> Result<()> {
+        let log = log.map(|log| log.phase("checkout"));
         match self {
             Entry::Remote { url, tools, .. } => {
                 let src = Resource::from_url(url)?;
-                src.update(&self.src_dir()?)?;
+                src.update(&self.src_dir()?, log.as_deref())?;
                 for tool in tools {
                     let src = Resource::from_url(&tool.url)?;
-                    src.update(&self.src_dir()?.join(tool.rel_path()))?;
+                    src.update(&self.src_dir()?.join(tool.rel_path()), log.as_deref())?;
                 }
             }
             Entry::Local { .. } => {}
@@ -563,21 +670,52 @@ impl Entry {
         }
     }
 
+    /// Directory which holds everything fetched for this entry (removed by `--discard`)
+    pub fn source_root(&self) -> Result<PathBuf> {
+        Ok(match self {
+            Entry::Remote { name, .. } => cache_dir()?.join(name),
+            Entry::Local { path, .. } => path.into(),
+        })
+    }
+
     pub fn src_dir(&self) -> Result<PathBuf> {
         Ok(match self {
-            Entry::Remote { name, .. } => {
+            Entry::Remote { .. } => {
                 if !self.setting().project {
-                    cache_dir()?.join(name).join("llvm")
+                    self.source_root()?.join("llvm")
                 } else {
-                    cache_dir()?.join(name)
+                    self.source_root()?
                 }
             }
             Entry::Local { path, .. } => path.into(),
         })
     }
 
+    /// Directory of the LLVM cmake project
+    fn llvm_dir(&self) -> Result<PathBuf> {
+        Ok(if self.setting().project {
+            self.src_dir()?.join("llvm")
+        } else {
+            self.src_dir()?
+        })
+    }
+
+    /// Source of a runtime (e.g. "compiler-rt", "runtimes") next to the LLVM source
+    pub fn runtime_source(&self, name: &str) -> Result<PathBuf> {
+        let llvm = self.llvm_dir()?;
+        Ok(match llvm.parent() {
+            Some(parent) => parent.join(name),
+            None => llvm.join("..").join(name),
+        })
+    }
+
+    /// Path of build dir. Different from [build_dir](#method.build_dir), this does not create it.
+    pub fn build_path(&self) -> Result<PathBuf> {
+        Ok(self.src_dir()?.join("build"))
+    }
+
     pub fn build_dir(&self) -> Result<PathBuf> {
-        let dir = self.src_dir()?.join("build");
+        let dir = self.build_path()?;
         if !dir.exists() {
             info!("Create build dir: {}", dir.display());
             fs::create_dir_all(&dir).with(&dir)?;
@@ -585,10 +723,15 @@ impl Entry {
         Ok(dir)
     }
 
+    /// Remove the build dirs of all stages
     pub fn clean_build_dir(&self) -> Result<()> {
-        let path = self.build_dir()?;
-        info!("Remove build dir: {}", path.display());
-        fs::remove_dir_all(&path).with(&path)?;
+        for stage in self.stages()? {
+            let path = stage.build_dir;
+            if path.exists() {
+                info!("Remove build dir: {}", path.display());
+                fs::remove_dir_all(&path).with(&path)?;
+            }
+        }
         Ok(())
     }
 
@@ -596,79 +739,775 @@ impl Entry {
         Ok(data_dir()?.join(self.name()))
     }
 
-    pub fn build(&self, nproc: usize) -> Result<()> {
-        self.configure()?;
-        let build_dir = self.build_dir()?;
-        info!("Build LLVM/Clang: {}", build_dir.display());
-        process::Command::new("cmake")
-            .args([
-                "--build",
-                &format!("{}", build_dir.display()),
-                "--target",
-                "install",
-            ])
-            .args(
-                self.setting()
-                    .generator
-                    .build_option(nproc, self.setting().build_type),
-            )
-            .check_run()?;
+    /// Setting of the bootstrap build. `None` for a single stage build.
+    ///
+    /// Optimized builds are always bootstrapped with lld.
+    pub fn bootstrap(&self) -> Option<Bootstrap> {
+        let setting = self.setting();
+        let mut bootstrap = match (&setting.bootstrap, setting.stages) {
+            (Some(bootstrap), _) => bootstrap.clone(),
+            (None, Some(stages)) => Bootstrap::new(stages),
+            (None, None) if setting.optimize.is_some() => Bootstrap::new(2),
+            (None, None) => return None,
+        };
+        if setting.optimize.is_some() {
+            bootstrap.stages = bootstrap.stages.max(2);
+            bootstrap.lld = true;
+        }
+        if bootstrap.stages > 1 {
+            Some(bootstrap)
+        } else {
+            None
+        }
+    }
+
+    /// Build whose clang compiles the first stage, set by `host_compiler`
+    pub fn host_compiler(&self) -> Result<Option<Build>> {
+        self.setting()
+            .host_compiler
+            .as_ref()
+            .map(|name| Build::from_name(name))
+            .transpose()
+    }
+
+    /// Check that the clang of [host_compiler](#method.host_compiler) is installed
+    fn check_host_compiler(&self) -> Result<()> {
+        if let Some(build) = self.host_compiler()? {
+            let clang = build.prefix().join("bin").join("clang");
+            if !clang.exists() {
+                return Err(Error::HostCompilerNotFound {
+                    name: build.name().into(),
+                    path: clang,
+                });
+            }
+            info!("Use clang of build {} as the host compiler", build.name());
+        }
         Ok(())
     }
 
-    fn configure(&self) -> Result<()> {
+    /// Stages of the build in execution order. The last one is installed into [prefix](#method.prefix).
+    ///
+    /// Each stage except the first one is compiled by the clang of the previous stage.
+    /// For PGO, an instrumented stage is inserted before the final one, which is compiled
+    /// by the same clang as the final stage.
+    pub fn stages(&self) -> Result<Vec<Stage>> {
         let setting = self.setting();
-        let mut opts = setting.generator.option();
-        let dir = if setting.project {
-            self.src_dir()?.join("llvm")
+        let mut stages = Vec::new();
+        let mut final_stage = Stage::final_stage(self.build_path()?, setting.build_type);
+        let host = match self.host_compiler()? {
+            Some(build) => host_compiler_option(&build.prefix().join("bin")),
+            None => BTreeMap::new(),
+        };
+        if let Some(toolchain) = self.toolchain()? {
+            let native = self.native_stage(host)?;
+            let mut option = BTreeMap::new();
+            option.insert(
+                "CMAKE_TOOLCHAIN_FILE".to_string(),
+                final_stage
+                    .build_dir
+                    .join(TOOLCHAIN_FILE)
+                    .display()
+                    .to_string(),
+            );
+            option.insert("CMAKE_SYSTEM_NAME".into(), toolchain.system_name().into());
+            if let Some(sysroot) = &toolchain.sysroot {
+                option.insert("CMAKE_SYSROOT".into(), sysroot.display().to_string());
+            }
+            option.insert("LLVM_HOST_TRIPLE".into(), toolchain.triple.clone());
+            option.insert(
+                "LLVM_TABLEGEN".into(),
+                native.bin_dir().join("llvm-tblgen").display().to_string(),
+            );
+            if self.has_clang() {
+                option.insert(
+                    "CLANG_TABLEGEN".into(),
+                    native.bin_dir().join("clang-tblgen").display().to_string(),
+                );
+            }
+            final_stage.option = option;
+            final_stage.toolchain = Some(toolchain);
+            stages.push(native);
+            stages.push(final_stage);
+            return Ok(stages);
+        }
+        let bootstrap = match self.bootstrap() {
+            Some(bootstrap) => bootstrap,
+            None => {
+                final_stage.option = host;
+                stages.push(final_stage);
+                return Ok(stages);
+            }
+        };
+        let pgo = setting.optimize.is_some_and(|o| o.pgo());
+        let mut option = bootstrap.stage_option(setting.project, &setting.option);
+        if pgo && setting.project {
+            // Profile runtime for the instrumented stage
+            let runtimes = append_list(
+                option
+                    .get("LLVM_ENABLE_RUNTIMES")
+                    .or(setting.option.get("LLVM_ENABLE_RUNTIMES")),
+                &["compiler-rt"],
+            );
+            option.insert("LLVM_ENABLE_RUNTIMES".into(), runtimes);
+        }
+        let stage_dir =
+            |n: u32| -> Result<PathBuf> { Ok(self.src_dir()?.join(format!("build-stage{}", n))) };
+        let mut prev: Option<PathBuf> = None;
+        for n in 1..bootstrap.stages {
+            let mut option = option.clone();
+            match &prev {
+                Some(bin) => option.extend(compiler_option(bin, bootstrap.lld, bootstrap.libcxx)),
+                None => {
+                    // Options in the bootstrap table are respected
+                    for (k, v) in &host {
+                        option.entry(k.clone()).or_insert_with(|| v.clone());
+                    }
+                }
+            }
+            let stage = Stage {
+                name: format!("stage{}", n),
+                build_dir: stage_dir(n)?,
+                build_type: bootstrap.build_type,
+                option,
+                install: false,
+                train: None,
+                targets: Vec::new(),
+                toolchain: None,
+            };
+            prev = Some(stage.bin_dir());
+            stages.push(stage);
+        }
+        // `bootstrap()` ensures at least one intermediate stage
+        let bin = prev.unwrap();
+        final_stage.option = compiler_option(&bin, bootstrap.lld, bootstrap.libcxx);
+        if pgo {
+            let n = bootstrap.stages;
+            let build_dir = stage_dir(n)?;
+            let mut option = option.clone();
+            option.extend(compiler_option(&bin, bootstrap.lld, bootstrap.libcxx));
+            option.insert("LLVM_BUILD_INSTRUMENTED".into(), "IR".into());
+            option.insert("LLVM_BUILD_RUNTIME".into(), "OFF".into());
+            let profile = build_dir.join("llvmenv.profdata");
+            final_stage
+                .option
+                .insert("LLVM_PROFDATA_FILE".into(), profile.display().to_string());
+            stages.push(Stage {
+                name: format!("stage{}", n),
+                build_dir,
+                build_type: BuildType::Release,
+                option,
+                install: false,
+                train: Some(Training {
+                    profdata: bin.join("llvm-profdata"),
+                    profile,
+                }),
+                targets: Vec::new(),
+                toolchain: None,
+            });
+        }
+        if setting.optimize.is_some_and(|o| o.lto()) {
+            final_stage
+                .option
+                .insert("LLVM_ENABLE_LTO".into(), "Thin".into());
+        }
+        stages.push(final_stage);
+        Ok(stages)
+    }
+
+    /// Toolchain of the cross-compilation. `None` if the entry is built for this machine.
+    pub fn toolchain(&self) -> Result<Option<Toolchain>> {
+        let setting = self.setting();
+        let triple = match &setting.host_triple {
+            Some(triple) => triple,
+            None => return Ok(None),
+        };
+        let clang_bin = self
+            .host_compiler()?
+            .map(|build| build.prefix().join("bin"));
+        let sysroot = setting
+            .sysroot
+            .as_ref()
+            .map(|path| PathBuf::from(shellexpand::full(path).unwrap().to_string()));
+        Ok(Some(Toolchain::new(
+            triple,
+            sysroot.as_deref(),
+            setting.cross_toolchain.as_deref(),
+            clang_bin.as_deref(),
+        )))
+    }
+
+    /// Stage which builds tablegen for this machine, used while cross-compiling
+    fn native_stage(&self, host: BTreeMap<String, String>) -> Result<Stage> {
+        let setting = self.setting();
+        let mut option = Bootstrap::new(2).stage_option(false, &setting.option);
+        option.extend(host);
+        let mut targets = vec!["llvm-tblgen".to_string()];
+        if self.has_clang() {
+            targets.push("clang-tblgen".into());
+            if setting.project {
+                option.insert("LLVM_ENABLE_PROJECTS".into(), append_list(None, &["clang"]));
+            }
+        }
+        Ok(Stage {
+            name: "native".into(),
+            build_dir: self.src_dir()?.join("build-native"),
+            build_type: BuildType::Release,
+            option,
+            install: false,
+            train: None,
+            targets,
+            toolchain: None,
+        })
+    }
+
+    /// Whether clang is built with LLVM
+    fn has_clang(&self) -> bool {
+        let setting = self.setting();
+        if setting.project {
+            return setting
+                .option
+                .get("LLVM_ENABLE_PROJECTS")
+                .is_some_and(|projects| projects.split(';').any(|p| p == "clang" || p == "all"));
+        }
+        match self {
+            Entry::Remote { tools, .. } => tools.iter().any(|tool| tool.name == "clang"),
+            Entry::Local { path, .. } => {
+                path.join("tools/clang").exists() || path.join("../clang").exists()
+            }
+        }
+    }
+
+    /// Resources downloaded by [checkout](#method.checkout) and their destinations
+    pub fn resources(&self) -> Result<Vec<PlannedResource>> {
+        Ok(match self {
+            Entry::Remote { url, tools, .. } => {
+                let mut res = vec![PlannedResource {
+                    url: url.clone(),
+                    dest: self.src_dir()?,
+                }];
+                for tool in tools {
+                    res.push(PlannedResource {
+                        url: tool.url.clone(),
+                        dest: self.src_dir()?.join(tool.rel_path()),
+                    });
+                }
+                res
+            }
+            Entry::Local { .. } => Vec::new(),
+        })
+    }
+
+    /// Describe what `llvmenv build-entry` will do, without any side effect
+    pub fn plan(
+        &self,
+        jobs: &Parallelism,
+        update: bool,
+        discard: bool,
+        reconfigure: bool,
+    ) -> Result<Plan> {
+        let root = self.source_root()?;
+        let fetch = discard || !root.exists();
+        let resources = if fetch { self.resources()? } else { Vec::new() };
+        let mut create_dirs: Vec<PathBuf> = resources
+            .iter()
+            .map(|res| res.dest.clone())
+            .filter(|dest| fetch || !dest.exists())
+            .collect();
+        let discarded = if discard { vec![root] } else { Vec::new() };
+        let mut commands = Vec::new();
+        for stage in self.stages()? {
+            if fetch || !stage.build_dir.exists() {
+                create_dirs.push(stage.build_dir.clone());
+            }
+            let configure = self.configure_command(&stage, Some(jobs))?;
+            let mut configure_plan = PlannedCommand::new(&stage.phase("configure"), &configure);
+            if !fetch && !reconfigure && self.configure_outdated(&stage)?.is_none() {
+                configure_plan.skip =
+                    Some("cmake inputs are unchanged since the last configure".into());
+            }
+            commands.push(configure_plan);
+            commands.push(PlannedCommand::new(
+                &stage.phase("build"),
+                &self.stage_build_command(&stage, jobs, None),
+            ));
+            if let Some(training) = &stage.train {
+                create_dirs.push(stage.training_dir());
+                create_dirs.push(stage.profiles_dir());
+                for cmd in self.training_commands(&stage, jobs)? {
+                    commands.push(PlannedCommand::new(&stage.phase("train"), &cmd));
+                }
+                commands.push(PlannedCommand::new(
+                    &stage.phase("merge"),
+                    &merge_command(&stage, training),
+                ));
+            }
+            if stage.install {
+                commands.push(PlannedCommand::new(
+                    &stage.phase("install"),
+                    &self.stage_build_command(&stage, jobs, Some("install")),
+                ));
+            }
+        }
+        Ok(Plan {
+            entry: self.name().into(),
+            remove_before: discarded.iter().filter(|p| p.exists()).cloned().collect(),
+            resources,
+            update,
+            create_dirs,
+            commands,
+            remove_after: discarded,
+        })
+    }
+
+    /// Configure and build every stage, then install the final stage into [prefix](#method.prefix)
+    ///
+    /// cmake is re-run only when the configure inputs have changed since the last run
+    /// (see [configure_hash](#method.configure_hash)), or `reconfigure` is true.
+    ///
+    /// Output of each phase is also written into `log` if given.
+    pub fn build(
+        &self,
+        jobs: &Parallelism,
+        reconfigure: bool,
+        log: Option<&BuildLog>,
+    ) -> Result<()> {
+        self.check_host_compiler()?;
+        let stages = self.stages()?;
+        let mut phases = Vec::new();
+        for stage in &stages {
+            if self.configure(stage, jobs, reconfigure, log)? {
+                phases.push(stage.phase("configure"));
+            }
+            if stage.name.is_empty() {
+                info!("Build LLVM/Clang: {}", stage.build_dir.display());
+            } else {
+                info!(
+                    "Build LLVM/Clang {}: {}",
+                    stage.name,
+                    stage.build_dir.display()
+                );
+            }
+            self.stage_build_command(stage, jobs, None)
+                .check_run_to(log.map(|log| log.phase(&stage.phase("build"))).as_deref())?;
+            phases.push(stage.phase("build"));
+            if let Some(training) = &stage.train {
+                self.train(stage, training, jobs, log)?;
+                phases.push(stage.phase("train"));
+                phases.push(stage.phase("merge"));
+            }
+            if stage.install {
+                info!("Install LLVM/Clang: {}", self.prefix()?.display());
+                let prefix = self.prefix()?;
+                let marker = prefix.join(INCOMPLETE_MARKER);
+                fs::create_dir_all(&prefix).with(&prefix)?;
+                fs::write(&marker, "").with(&marker)?;
+                self.stage_build_command(stage, jobs, Some("install"))
+                    .check_run_to(log.map(|log| log.phase(&stage.phase("install"))).as_deref())?;
+                phases.push(stage.phase("install"));
+            }
+        }
+        let mut manifest = Manifest::reinstalled(self, &self.prefix()?)?;
+        manifest.stages = self.bootstrap().map(|bootstrap| bootstrap.stages);
+        manifest.optimize = self.setting().optimize.map(|o| o.to_string());
+        manifest.foreign_host = self.setting().host_triple.clone();
+        manifest.phases = phases;
+        manifest.save(&self.prefix()?)?;
+        let marker = self.prefix()?.join(INCOMPLETE_MARKER);
+        if marker.exists() {
+            fs::remove_file(&marker).with(&marker)?;
+        }
+        Ok(())
+    }
+
+    /// Run the PGO training workload with the instrumented stage, and merge the profiles
+    fn train(
+        &self,
+        stage: &Stage,
+        training: &Training,
+        jobs: &Parallelism,
+        log: Option<&BuildLog>,
+    ) -> Result<()> {
+        // Profiles written while building the stage, e.g. by llvm-tblgen, are discarded
+        for dir in [stage.training_dir(), stage.profiles_dir()] {
+            if dir.exists() {
+                fs::remove_dir_all(&dir).with(&dir)?;
+            }
+            fs::create_dir_all(&dir).with(&dir)?;
+        }
+        info!(
+            "Run PGO training workload: {}",
+            stage.training_dir().display()
+        );
+        let train_log = log.map(|log| log.phase(&stage.phase("train")));
+        for mut cmd in self.training_commands(stage, jobs)? {
+            cmd.check_run_to(train_log.as_deref())?;
+        }
+        let profiles = stage.profiles_dir();
+        let found = fs::read_dir(&profiles)
+            .with(&profiles)?
+            .flatten()
+            .any(|f| f.path().extension().is_some_and(|ext| ext == "profraw"));
+        if !found {
+            return Err(Error::ProfileNotFound { path: profiles });
+        }
+        info!("Merge profiles into {}", training.profile.display());
+        merge_command(stage, training)
+            .check_run_to(log.map(|log| log.phase(&stage.phase("merge"))).as_deref())?;
+        Ok(())
+    }
+
+    /// Commands of the PGO training workload, run with `CC` and `CXX` set to the instrumented clang
+    ///
+    /// The default workload compiles LLVMCore, which also runs the instrumented clang over LLVMSupport.
+    fn training_commands(
+        &self,
+        stage: &Stage,
+        jobs: &Parallelism,
+    ) -> Result<Vec<process::Command>> {
+        let setting = self.setting();
+        let bin = stage.bin_dir();
+        let dir = stage.training_dir();
+        let mut cmds = Vec::new();
+        if setting.training.is_empty() {
+            let mut configure = process::Command::new("cmake");
+            configure
+                .args(setting.generator.option())
+                .arg(self.llvm_dir()?)
+                .arg(format!(
+                    "-DCMAKE_C_COMPILER={}",
+                    bin.join("clang").display()
+                ))
+                .arg(format!(
+                    "-DCMAKE_CXX_COMPILER={}",
+                    bin.join("clang++").display()
+                ))
+                .arg("-DCMAKE_BUILD_TYPE=Release")
+                .arg("-DLLVM_TARGETS_TO_BUILD=Native")
+                .arg("-DLLVM_INCLUDE_TESTS=OFF");
+            cmds.push(configure);
+            let mut build = process::Command::new("cmake");
+            build
+                .arg("--build")
+                .arg(&dir)
+                .args(["--target", "LLVMCore"])
+                .args(
+                    setting
+                        .generator
+                        .build_option(jobs.compile, BuildType::Release),
+                );
+            cmds.push(build);
         } else {
-            self.src_dir()?
+            for line in &setting.training {
+                let mut cmd = process::Command::new("sh");
+                cmd.arg("-c").arg(line);
+                cmds.push(cmd);
+            }
+        }
+        for cmd in &mut cmds {
+            cmd.current_dir(&dir)
+                .env("CC", bin.join("clang"))
+                .env("CXX", bin.join("clang++"))
+                .env(
+                    "LLVM_PROFILE_FILE",
+                    stage.profiles_dir().join("%4m.profraw"),
+                );
+        }
+        Ok(cmds)
+    }
+
+    /// `cmake --build` command for the target in the build dir. `None` means the default target.
+    pub fn build_command(
+        &self,
+        jobs: &Parallelism,
+        target: Option<&str>,
+    ) -> Result<process::Command> {
+        let stage = self.stages()?.pop().unwrap();
+        Ok(self.stage_build_command(&stage, jobs, target))
+    }
+
+    fn stage_build_command(
+        &self,
+        stage: &Stage,
+        jobs: &Parallelism,
+        target: Option<&str>,
+    ) -> process::Command {
+        let mut cmd = process::Command::new("cmake");
+        cmd.arg("--build").arg(&stage.build_dir);
+        if let Some(target) = target {
+            cmd.args(["--target", target]);
+        } else if !stage.targets.is_empty() {
+            cmd.arg("--target").args(&stage.targets);
+        }
+        cmd.args(
+            self.setting()
+                .generator
+                .build_option(jobs.compile, stage.build_type),
+        );
+        cmd
+    }
+
+    /// Run cmake configure of the stage if required. Returns whether it is run.
+    fn configure(
+        &self,
+        stage: &Stage,
+        jobs: &Parallelism,
+        reconfigure: bool,
+        log: Option<&BuildLog>,
+    ) -> Result<bool> {
+        let build_dir = &stage.build_dir;
+        if !build_dir.exists() {
+            info!("Create build dir: {}", build_dir.display());
+            fs::create_dir_all(build_dir).with(build_dir)?;
+        }
+        if reconfigure {
+            info!("Reconfigure is requested");
+        } else {
+            match self.configure_outdated(stage)? {
+                Some(reason) => info!("{}", reason),
+                None => {
+                    info!(
+                        "Skip configure since cmake inputs are unchanged: {}",
+                        build_dir.display()
+                    );
+                    if self.setting().generator == CMakeGenerator::Ninja {
+                        info!("Parallel link jobs of the last configure are kept. Use --reconfigure to update them");
+                    }
+                    return Ok(false);
+                }
+            }
+        }
+        let hash_file = build_dir.join(CONFIGURE_HASH);
+        // Remove the old hash first not to skip configure after a failure
+        if hash_file.exists() {
+            fs::remove_file(&hash_file).with(&hash_file)?;
+        }
+        if let Some(toolchain) = &stage.toolchain {
+            let path = build_dir.join(TOOLCHAIN_FILE);
+            info!("Write cmake toolchain file: {}", path.display());
+            fs::write(&path, toolchain.cmake()).with(&path)?;
+        }
+        self.configure_command(stage, Some(jobs))?.check_run_to(
+            log.map(|log| log.phase(&stage.phase("configure")))
+                .as_deref(),
+        )?;
+        fs::write(&hash_file, self.stage_configure_hash(stage)?).with(&hash_file)?;
+        Ok(true)
+    }
+
+    /// Check the configure inputs against the hash stored by the last successful configure.
+    /// Returns why configure is required, or `None` if it can be skipped.
+    fn configure_outdated(&self, stage: &Stage) -> Result<Option<String>> {
+        let build_dir = &stage.build_dir;
+        if !build_dir.join("CMakeCache.txt").exists() {
+            return Ok(Some("No cmake cache found, configure is required".into()));
+        }
+        let hash_file = build_dir.join(CONFIGURE_HASH);
+        let stored = match fs::read_to_string(&hash_file) {
+            Ok(stored) => stored,
+            Err(_) => {
+                return Ok(Some(
+                    "No configure hash found, configure is required".into(),
+                ))
+            }
         };
-        opts.push(format!("{}", dir.display()));
+        let current = self.stage_configure_hash(stage)?;
+        if stored.trim() != current {
+            return Ok(Some(format!(
+                "cmake inputs have changed ({} -> {}), configure is required",
+                stored.trim(),
+                current
+            )));
+        }
+        Ok(None)
+    }
 
-        opts.push(format!(
-            "-DCMAKE_INSTALL_PREFIX={}",
-            data_dir()?.join(self.prefix()?).display()
-        ));
+    /// Hash of every input of the configure step of the final stage
+    ///
+    /// - cmake option list, which contains the generator, install prefix and build type
+    /// - host C/C++ compilers
+    /// - commit of the source if it is a git repository, or the URLs of the resources
+    ///
+    /// Parallel job settings are not included since they depend on the memory available at that time.
+    pub fn configure_hash(&self) -> Result<String> {
+        self.stage_configure_hash(&self.stages()?.pop().unwrap())
+    }
+
+    fn stage_configure_hash(&self, stage: &Stage) -> Result<String> {
+        // SHA-256 is used since std hashers are not stable across Rust releases
+        let mut hasher = Sha256::new();
+        let mut input = |bytes: &[u8]| {
+            hasher.update(bytes);
+            hasher.update([0]);
+        };
+        for arg in self.configure_command(stage, None)?.get_args() {
+            input(arg.as_encoded_bytes());
+        }
+        for (var, default) in [("CC", "cc"), ("CXX", "c++")] {
+            let compiler = env::var(var).unwrap_or_else(|_| default.into());
+            input(compiler.as_bytes());
+            match which::which(&compiler) {
+                Ok(path) => input(path.as_os_str().as_encoded_bytes()),
+                Err(_) => input(b"(not found)"),
+            }
+        }
+        if let Entry::Remote { url, tools, .. } = self {
+            input(url.as_bytes());
+            for tool in tools {
+                input(tool.url.as_bytes());
+            }
+        }
+        if let Some(toolchain) = &stage.toolchain {
+            input(toolchain.cmake().as_bytes());
+        }
+        if let Some(commit) = self.source_commit() {
+            input(commit.as_bytes());
+        }
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
 
-        opts.push(format!("-DCMAKE_BUILD_TYPE={:?}", setting.build_type));
+    /// Commit hash of the source if it is managed by git
+    fn source_commit(&self) -> Option<String> {
+        git_head(&self.src_dir().ok()?)
+    }
+
+    /// cmake command line of the configure step of the stage.
+    /// Parallel job settings are added if `jobs` is given.
+    fn configure_command(
+        &self,
+        stage: &Stage,
+        jobs: Option<&Parallelism>,
+    ) -> Result<process::Command> {
+        let setting = self.setting();
+        let mut opts = setting.generator.option();
+        opts.push(format!("{}", self.llvm_dir()?.display()));
+
+        // `-D` options. Options of the stage overwrite the others.
+        let mut defs: Vec<(String, String)> = Vec::new();
+        let mut define = |key: &str, value: String| {
+            if !stage.option.contains_key(key) {
+                defs.push((key.into(), value));
+            }
+        };
+
+        define(
+            "CMAKE_INSTALL_PREFIX",
+            data_dir()?.join(self.prefix()?).display().to_string(),
+        );
+
+        define("CMAKE_BUILD_TYPE", format!("{:?}", stage.build_type));
 
         // Enable ccache if exists
         if which::which("ccache").is_ok() {
-            opts.push("-DLLVM_CCACHE_BUILD=ON".into());
+            define("LLVM_CCACHE_BUILD", "ON".into());
         }
 
-        // Enable lld if exists
-        if which::which("lld").is_ok() {
-            opts.push("-DLLVM_ENABLE_LLD=ON".into());
+        // Enable lld if exists. Cross compilers may not support it.
+        if stage.toolchain.is_none() && which::which("lld").is_ok() {
+            define("LLVM_ENABLE_LLD", "ON".into());
         }
 
         // Target architectures
         if !setting.target.is_empty() {
-            opts.push(format!(
-                "-DLLVM_TARGETS_TO_BUILD={}",
-                setting.target.iter().join(";")
-            ));
+            define("LLVM_TARGETS_TO_BUILD", setting.target.iter().join(";"));
+        }
+
+        // Parallel compile/link jobs are available only with Ninja
+        if let Some(jobs) = jobs {
+            if setting.generator == CMakeGenerator::Ninja {
+                for (key, value) in [
+                    ("LLVM_PARALLEL_COMPILE_JOBS", jobs.compile),
+                    ("LLVM_PARALLEL_LINK_JOBS", jobs.link),
+                ] {
+                    if !setting.option.contains_key(key) {
+                        define(key, value.to_string());
+                    }
+                }
+            }
         }
 
-        // Other options
-        for (k, v) in &setting.option {
+        // Other options (sorted to keep the command line stable)
+        for (k, v) in setting.option.iter().sorted() {
+            define(k, v.clone());
+        }
+        for (k, v) in &stage.option {
+            defs.push((k.clone(), v.clone()));
+        }
+        for (k, v) in defs {
             opts.push(format!("-D{}={}", k, v));
         }
 
-        process::Command::new("cmake")
-            .args(&opts)
-            .current_dir(self.build_dir()?)
-            .check_run()?;
+        let mut cmd = process::Command::new("cmake");
+        cmd.args(&opts).current_dir(&stage.build_dir);
+        Ok(cmd)
+    }
+}
 
-        Ok(())
+/// `llvm-profdata merge` of the raw profiles written by the training of the stage
+fn merge_command(stage: &Stage, training: &Training) -> process::Command {
+    let mut cmd = process::Command::new(&training.profdata);
+    cmd.arg("merge")
+        .arg(format!("-output={}", training.profile.display()))
+        .arg(stage.profiles_dir());
+    cmd
+}
+
+/// HEAD of the git repository whose top is `dir`.
+/// A plain directory inside another repository, e.g. an expanded tarball under a dotfiles repo, has no commit.
+fn git_head(dir: &Path) -> Option<String> {
+    let git = |args: &[&str]| {
+        process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .check_output()
+            .ok()
+            .map(|(stdout, _)| stdout.trim().to_string())
+    };
+    let top = fs::canonicalize(git(&["rev-parse", "--show-toplevel"])?).ok()?;
+    if top != fs::canonicalize(dir).ok()? {
+        return None;
     }
+    git(&["rev-parse", "HEAD"])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn no_commit_of_plain_dir_in_repository() {
+        let repo = tempfile::TempDir::new().unwrap();
+        let git = |dir: &Path, args: &[&str]| {
+            process::Command::new("git")
+                .args(args)
+                .current_dir(dir)
+                .check_output()
+                .unwrap();
+        };
+        git(repo.path(), &["init", "-q"]);
+        git(
+            repo.path(),
+            &[
+                "-c",
+                "user.name=llvmenv",
+                "-c",
+                "user.email=llvmenv@example.com",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "init",
+            ],
+        );
+        let src = repo.path().join("llvm-project");
+        fs::create_dir(&src).unwrap();
+        assert!(git_head(repo.path()).is_some());
+        assert_eq!(git_head(&src), None);
+    }
+
     #[test]
     fn parse_url() {
         let setting = EntrySetting {
@@ -727,13 +1566,273 @@ mod tests {
         )
     }
 
+    #[test]
+    fn plan_local() -> Result<()> {
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm".into()),
+            generator: CMakeGenerator::Ninja,
+            target: vec!["X86".into()],
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("local", None, setting)?;
+        let jobs = Parallelism {
+            compile: 4,
+            link: 2,
+        };
+        let plan = entry.plan(&jobs, false, false, false)?;
+        assert!(plan.resources.is_empty());
+        assert!(plan.remove_before.is_empty());
+        assert_eq!(plan.commands.len(), 3);
+
+        let configure = &plan.commands[0];
+        assert_eq!(configure.phase, "configure");
+        assert_eq!(configure.cwd, Some(PathBuf::from("/path/to/llvm/build")));
+        assert_eq!(configure.args[..3], ["-G", "Ninja", "/path/to/llvm"]);
+        assert!(configure
+            .args
+            .contains(&"-DLLVM_TARGETS_TO_BUILD=X86".to_string()));
+        assert!(configure
+            .args
+            .contains(&"-DLLVM_PARALLEL_LINK_JOBS=2".to_string()));
+
+        let build = &plan.commands[1];
+        assert_eq!(build.phase, "build");
+        assert_eq!(build.args[build.args.len() - 2..], ["-j", "4"]);
+        assert!(!build.args.contains(&"install".to_string()));
+
+        let install = &plan.commands[2];
+        assert_eq!(install.phase, "install");
+        assert_eq!(install.args[2..4], ["--target", "install"]);
+        Ok(())
+    }
+
+    #[test]
+    fn plan_bootstrap() -> Result<()> {
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm".into()),
+            target: vec!["X86".into()],
+            build_type: BuildType::Debug,
+            stages: Some(2),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("local", None, setting)?;
+        let jobs = Parallelism {
+            compile: 4,
+            link: 4,
+        };
+        let plan = entry.plan(&jobs, false, false, false)?;
+        let phases: Vec<_> = plan.commands.iter().map(|c| c.phase.as_str()).collect();
+        assert_eq!(
+            phases,
+            [
+                "stage1-configure",
+                "stage1-build",
+                "configure",
+                "build",
+                "install"
+            ]
+        );
+
+        let stage1 = &plan.commands[0];
+        assert_eq!(
+            stage1.cwd,
+            Some(PathBuf::from("/path/to/llvm/build-stage1"))
+        );
+        assert!(stage1
+            .args
+            .contains(&"-DLLVM_TARGETS_TO_BUILD=Native".to_string()));
+        assert!(!stage1
+            .args
+            .contains(&"-DLLVM_TARGETS_TO_BUILD=X86".to_string()));
+        assert!(stage1
+            .args
+            .contains(&"-DCMAKE_BUILD_TYPE=Release".to_string()));
+
+        let last = &plan.commands[2];
+        assert_eq!(last.cwd, Some(PathBuf::from("/path/to/llvm/build")));
+        assert!(last
+            .args
+            .contains(&"-DCMAKE_C_COMPILER=/path/to/llvm/build-stage1/bin/clang".to_string()));
+        assert!(last.args.contains(&"-DCMAKE_BUILD_TYPE=Debug".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn plan_pgo_lto() -> Result<()> {
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm-project".into()),
+            project: true,
+            optimize: Some(Optimize::PgoLto),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("local", None, setting)?;
+        let jobs = Parallelism {
+            compile: 4,
+            link: 4,
+        };
+        let plan = entry.plan(&jobs, false, false, false)?;
+        let phases: Vec<_> = plan.commands.iter().map(|c| c.phase.as_str()).collect();
+        assert_eq!(
+            phases,
+            [
+                "stage1-configure",
+                "stage1-build",
+                "stage2-configure",
+                "stage2-build",
+                "stage2-train",
+                "stage2-train",
+                "stage2-merge",
+                "configure",
+                "build",
+                "install"
+            ]
+        );
+        let has = |i: usize, arg: &str| plan.commands[i].args.contains(&arg.to_string());
+        assert!(has(0, "-DLLVM_ENABLE_PROJECTS=clang;lld"));
+        assert!(has(0, "-DLLVM_ENABLE_RUNTIMES=compiler-rt"));
+        assert!(has(2, "-DLLVM_BUILD_INSTRUMENTED=IR"));
+        assert!(has(
+            2,
+            "-DCMAKE_C_COMPILER=/path/to/llvm-project/build-stage1/bin/clang"
+        ));
+        assert_eq!(
+            plan.commands[6].program,
+            "/path/to/llvm-project/build-stage1/bin/llvm-profdata"
+        );
+        assert!(has(
+            7,
+            "-DLLVM_PROFDATA_FILE=/path/to/llvm-project/build-stage2/llvmenv.profdata"
+        ));
+        assert!(has(7, "-DLLVM_ENABLE_LTO=Thin"));
+        assert!(has(7, "-DLLVM_ENABLE_LLD=ON"));
+        assert!(!has(7, "-DLLVM_BUILD_INSTRUMENTED=IR"));
+        Ok(())
+    }
+
+    #[test]
+    fn host_compiler_compiles_first_stage() -> Result<()> {
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm".into()),
+            host_compiler: Some("system".into()),
+            stages: Some(2),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("local", None, setting)?;
+        let stages = entry.stages()?;
+        assert_eq!(stages[0].option["CMAKE_C_COMPILER"], "/usr/bin/clang");
+        assert_eq!(stages[0].option["CMAKE_CXX_COMPILER"], "/usr/bin/clang++");
+        assert_eq!(
+            stages[1].option["CMAKE_C_COMPILER"],
+            "/path/to/llvm/build-stage1/bin/clang"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn plan_cross() -> Result<()> {
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm-project".into()),
+            project: true,
+            option: [("LLVM_ENABLE_PROJECTS".into(), "clang;lld".into())].into(),
+            host_triple: Some("aarch64-linux-gnu".into()),
+            sysroot: Some("/usr/aarch64-linux-gnu".into()),
+            cross_toolchain: Some("aarch64-linux-gnu-".into()),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("cross", None, setting)?;
+        let jobs = Parallelism {
+            compile: 4,
+            link: 4,
+        };
+        let plan = entry.plan(&jobs, false, false, false)?;
+        let phases: Vec<_> = plan.commands.iter().map(|c| c.phase.as_str()).collect();
+        assert_eq!(
+            phases,
+            [
+                "native-configure",
+                "native-build",
+                "configure",
+                "build",
+                "install"
+            ]
+        );
+        let native_build = &plan.commands[1].args;
+        assert_eq!(
+            native_build[2..5],
+            ["--target", "llvm-tblgen", "clang-tblgen"]
+        );
+
+        let has = |arg: &str| plan.commands[2].args.contains(&arg.to_string());
+        assert!(has(
+            "-DCMAKE_TOOLCHAIN_FILE=/path/to/llvm-project/build/llvmenv-toolchain.cmake"
+        ));
+        assert!(has("-DCMAKE_SYSTEM_NAME=Linux"));
+        assert!(has("-DCMAKE_SYSROOT=/usr/aarch64-linux-gnu"));
+        assert!(has("-DLLVM_HOST_TRIPLE=aarch64-linux-gnu"));
+        assert!(has(
+            "-DLLVM_TABLEGEN=/path/to/llvm-project/build-native/bin/llvm-tblgen"
+        ));
+        assert!(has(
+            "-DCLANG_TABLEGEN=/path/to/llvm-project/build-native/bin/clang-tblgen"
+        ));
+        assert_eq!(
+            entry.toolchain()?.unwrap().cxx_compiler,
+            "aarch64-linux-gnu-g++"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_stages() {
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm".into()),
+            stages: Some(3),
+            bootstrap: Some(Bootstrap::new(2)),
+            ..Default::default()
+        };
+        assert!(Entry::parse_setting("local", None, setting).is_err());
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm".into()),
+            stages: Some(0),
+            ..Default::default()
+        };
+        assert!(Entry::parse_setting("local", None, setting).is_err());
+        let setting = EntrySetting {
+            path: Some("/path/to/llvm".into()),
+            host_triple: Some("aarch64-linux-gnu".into()),
+            optimize: Some(Optimize::Lto),
+            ..Default::default()
+        };
+        assert!(Entry::parse_setting("local", None, setting).is_err());
+    }
+
+    #[test]
+    fn configure_hash_follows_options() -> Result<()> {
+        let mut setting = EntrySetting {
+            path: Some("/path/to/llvm".into()),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("local", None, setting.clone())?;
+        let hash = entry.configure_hash()?;
+        assert_eq!(hash, entry.configure_hash()?);
+        // SHA-256 in hex
+        assert_eq!(hash.len(), 64);
+
+        setting
+            .option
+            .insert("LLVM_ENABLE_ASSERTIONS".into(), "ON".into());
+        let entry = Entry::parse_setting("local", None, setting)?;
+        assert_ne!(hash, entry.configure_hash()?);
+        Ok(())
+    }
+
     macro_rules! checkout {
         ($major:expr, $minor:expr, $patch: expr) => {
             paste::item! {
                 #[ignore]
                 #[test]
                 fn [< checkout_ $major _ $minor _ $patch >]() {
-                    Entry::official($major, $minor, $patch).checkout().unwrap();
+                    Entry::official($major, $minor, $patch).checkout(None).unwrap();
                 }
             }
         };
@@ -1,6 +1,15 @@
-use std::{io, path::*, process};
+use std::{
+    fs,
+    io::{self, Read, Seek, Write},
+    path::*,
+    process,
+    sync::{Arc, Mutex},
+    thread,
+};
 use thiserror::Error;
 
+use crate::diagnose::*;
+
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 #[derive(Debug, Error)]
@@ -38,9 +47,79 @@ pub enum Error {
         source: toml::de::Error,
     },
 
+    #[error(transparent)]
+    TOMLSerialize {
+        #[from]
+        source: toml::ser::Error,
+    },
+
+    #[error(transparent)]
+    InvalidJSON {
+        #[from]
+        source: serde_json::Error,
+    },
+
     #[error("Entry {name} is invalid: {message}")]
     InvalidEntry { name: String, message: String },
 
+    #[error("Build directory of entry {name} does not exist: {path}")]
+    BuildDirNotFound { name: String, path: PathBuf },
+
+    #[error("system is not managed by llvmenv")]
+    SystemBuild,
+
+    #[error("Build {name} is used by {}. Use --force to remove it anyway", .paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", "))]
+    BuildInUse { name: String, paths: Vec<PathBuf> },
+
+    #[error(
+        "{tool} is not found in build {build} ({setting}). Builds which provide it: [{providers}]"
+    )]
+    ToolNotFound {
+        tool: String,
+        build: String,
+        setting: String,
+        providers: String,
+    },
+
+    #[error("Build {name} set by {setting} does not exist")]
+    BuildNotFound { name: String, setting: String },
+
+    #[error("Archive of build {name} is not available: {url}")]
+    BinaryNotAvailable { name: String, url: String },
+
+    #[error("Preflight check of {name} failed. Run `llvmenv doctor {name}` for detail")]
+    PreflightFailed { name: String },
+
+    #[error("Invalid alias {alias}: {message}")]
+    InvalidAlias { alias: String, message: String },
+
+    #[error("Aliases are cyclic: {chain}")]
+    AliasCycle { chain: String },
+
+    #[error("Cannot list the official releases. Check the network connection to github.com")]
+    OfficialReleasesUnavailable,
+
+    #[error("Cannot link {name}: {message}")]
+    InvalidLink { name: String, message: String },
+
+    #[error("Build {name} is installed outside of llvmenv. Use `llvmenv unlink` to unregister it")]
+    LinkedBuild { name: String },
+
+    #[error("Build {name} runs on {host}, and cannot be used on this machine")]
+    ForeignHostBuild { name: String, host: String },
+
+    #[error("Entry of build {name} is unknown since it has no llvmenv-manifest.toml")]
+    UnknownEntryOfBuild { name: String },
+
+    #[error("Source of {name} is not found: {path}. Build the entry again without --discard")]
+    RuntimeSourceNotFound { name: String, path: PathBuf },
+
+    #[error("clang of the host compiler build {name} is not found: {path}")]
+    HostCompilerNotFound { name: String, path: PathBuf },
+
+    #[error("No profile is written by the PGO training workload into {path}")]
+    ProfileNotFound { path: PathBuf },
+
     #[error("HTTP request does not succeed with {status}: {url}")]
     HttpError {
         url: String,
@@ -65,28 +144,63 @@ pub enum Error {
         source: reqwest::Error,
     },
 
-    #[error("External command exit with error-code({errno}): {cmd}")]
+    #[error("External command exit with error-code({errno}): {cmd}{}{}", log_summary(.log), hint_summary(.hints))]
     CommandError {
         errno: i32,
         cmd: String,
         stdout: Option<String>,
         stderr: Option<String>,
+        log: Option<Box<CommandLog>>,
+        /// Known failures found in the output, see [diagnose](../diagnose/index.html)
+        hints: Vec<Hint>,
     },
 
     #[error("External command not found: {cmd}")]
     CommandNotFound { cmd: String },
 
-    #[error("External command has been terminated by signal: {cmd}")]
+    #[error("External command has been terminated by signal: {cmd}{}", log_summary(.log))]
     CommandTerminatedBySignal {
         cmd: String,
         stdout: Option<String>,
         stderr: Option<String>,
+        log: Option<Box<CommandLog>>,
     },
 
     #[error("Unsupported shell type: {shell}")]
     UnsupportedShell { shell: String },
 }
 
+/// Log file which captured the output of a failed command
+#[derive(Debug)]
+pub struct CommandLog {
+    pub path: PathBuf,
+    /// Last lines of the log file
+    pub tail: Vec<String>,
+}
+
+fn log_summary(log: &Option<Box<CommandLog>>) -> String {
+    let mut summary = String::new();
+    if let Some(log) = log {
+        summary.push_str(&format!("\nFull log: {}", log.path.display()));
+        if !log.tail.is_empty() {
+            summary.push_str(&format!("\nLast {} lines of output:", log.tail.len()));
+            for line in &log.tail {
+                summary.push_str("\n  ");
+                summary.push_str(line);
+            }
+        }
+    }
+    summary
+}
+
+fn hint_summary(hints: &[Hint]) -> String {
+    let mut summary = String::new();
+    for hint in hints {
+        summary.push_str(&format!("\nHint: {}", hint));
+    }
+    summary
+}
+
 impl Error {
     pub fn invalid_version(version: &str) -> Self {
         Error::InvalidVersion {
@@ -108,10 +222,22 @@ impl<T> FileIoConvert<T> for ::std::result::Result<T, io::Error> {
     }
 }
 
+/// Number of lines of a log file attached to [Error::CommandError]
+const LOG_TAIL_LINES: usize = 30;
+
 pub trait CommandExt {
     fn silent(&mut self) -> &mut Self;
     fn check_run(&mut self) -> Result<()>;
     fn check_output(&mut self) -> Result<(String, String)>;
+    /// Same as `check_run`, but stdout and stderr are also appended into `log`
+    fn check_run_logged(&mut self, log: &Path) -> Result<()>;
+    /// `check_run_logged` if `log` is given, `check_run` otherwise
+    fn check_run_to(&mut self, log: Option<&Path>) -> Result<()> {
+        match log {
+            Some(log) => self.check_run_logged(log),
+            None => self.check_run(),
+        }
+    }
 }
 
 impl CommandExt for process::Command {
@@ -133,6 +259,8 @@ impl CommandExt for process::Command {
                         cmd,
                         stdout: None,
                         stderr: None,
+                        log: None,
+                        hints: Vec::new(),
                     })
                 } else {
                     Ok(())
@@ -142,6 +270,7 @@ impl CommandExt for process::Command {
                 cmd,
                 stdout: None,
                 stderr: None,
+                log: None,
             }),
         }
     }
@@ -156,11 +285,14 @@ impl CommandExt for process::Command {
         match output.status.code() {
             Some(errno) => {
                 if errno != 0 {
+                    let hints = diagnose(&format!("{}\n{}", stdout, stderr));
                     Err(Error::CommandError {
                         errno,
                         cmd,
                         stdout: Some(stdout),
                         stderr: Some(stderr),
+                        log: None,
+                        hints,
                     })
                 } else {
                     Ok((stdout, stderr))
@@ -170,7 +302,121 @@ impl CommandExt for process::Command {
                 cmd,
                 stdout: Some(stdout),
                 stderr: Some(stderr),
+                log: None,
+            }),
+        }
+    }
+
+    fn check_run_logged(&mut self, log: &Path) -> Result<()> {
+        let cmd = format!("{:?}", self);
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log)
+            .with(log)?;
+        writeln!(file, "$ {}", cmd).with(log)?;
+        // The log may be shared with previous commands, whose output should not be reported
+        let offset = file.metadata().with(log)?.len();
+        let file = Arc::new(Mutex::new(file));
+        let mut child = self
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .map_err(|_| Error::CommandNotFound { cmd: cmd.clone() })?;
+        let stdout = tee(child.stdout.take().unwrap(), io::stdout(), file.clone());
+        let stderr = tee(child.stderr.take().unwrap(), io::stderr(), file);
+        let st = child.wait().with(log)?;
+        stdout.join().expect("stdout thread panicked");
+        stderr.join().expect("stderr thread panicked");
+        let output = read_from(log, offset).with(log)?;
+        let output = String::from_utf8_lossy(&output);
+        let captured = Box::new(CommandLog {
+            path: log.into(),
+            tail: tail_lines(&output, LOG_TAIL_LINES),
+        });
+        match st.code() {
+            Some(0) => Ok(()),
+            Some(errno) => Err(Error::CommandError {
+                errno,
+                cmd,
+                stdout: None,
+                stderr: None,
+                log: Some(captured),
+                hints: diagnose(&output),
+            }),
+            None => Err(Error::CommandTerminatedBySignal {
+                cmd,
+                stdout: None,
+                stderr: None,
+                log: Some(captured),
             }),
         }
     }
 }
+
+/// Copy `input` into both `output` and `file` in background
+fn tee(
+    mut input: impl Read + Send + 'static,
+    mut output: impl Write + Send + 'static,
+    file: Arc<Mutex<fs::File>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = match input.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let _ = output.write_all(&buf[..n]);
+            let _ = output.flush();
+            let _ = file.lock().unwrap().write_all(&buf[..n]);
+        }
+    })
+}
+
+/// Output of a command scanned on failure. The beginning of a larger output is not read.
+const LOG_SCAN_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Content of the file after `offset`, up to the last [LOG_SCAN_BYTES] bytes
+fn read_from(path: &Path, offset: u64) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(io::SeekFrom::Start(
+        offset.max(len.saturating_sub(LOG_SCAN_BYTES)),
+    ))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Last `n` lines of a text
+fn tail_lines(text: &str, n: usize) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_only_failed_command() -> Result<()> {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log = dir.path().join("build.log");
+        process::Command::new("sh")
+            .args(["-c", "echo succeeded"])
+            .check_run_logged(&log)?;
+        let err = process::Command::new("sh")
+            .args(["-c", "echo failed; exit 1"])
+            .check_run_logged(&log)
+            .unwrap_err();
+        match err {
+            Error::CommandError { log: Some(log), .. } => assert_eq!(log.tail, ["failed"]),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        Ok(())
+    }
+}
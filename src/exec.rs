@@ -0,0 +1,99 @@
+//! Run commands with a build without the shell integration
+//!
+//! - `llvmenv which {tool}` prints the path of the tool in the active build
+//! - `llvmenv exec [--build {name}] -- {command}` runs the command with the environment of the build:
+//!   `PATH` and `LD_LIBRARY_PATH` prefixed by the build, `LLVM_CONFIG_PATH`,
+//!   and `LLVM_SYS_{major}{minor}_PREFIX` for the llvm-sys crate
+
+use std::{
+    env,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{build::*, error::*};
+
+/// Path of the tool in the build, `None` if the build does not provide it
+pub fn which(build: &Build, tool: &str) -> Option<PathBuf> {
+    let path = build.prefix().join("bin").join(tool);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Builds which provide the tool
+pub fn providers(tool: &str) -> Result<Vec<Build>> {
+    Ok(builds()?
+        .into_iter()
+        .filter(|build| which(build, tool).is_some())
+        .collect())
+}
+
+/// Environment variables to use the build
+pub fn build_env(build: &Build) -> Result<Vec<(String, OsString)>> {
+    let prefix = build.prefix();
+    let mut vars = Vec::new();
+    // Keep /usr/bin in its place for the system build
+    if prefix != Path::new("/usr") {
+        vars.push((
+            "PATH".to_string(),
+            prepend_path("PATH", &prefix.join("bin"))?,
+        ));
+        vars.push((
+            "LD_LIBRARY_PATH".to_string(),
+            prepend_path("LD_LIBRARY_PATH", &prefix.join("lib"))?,
+        ));
+    }
+    let llvm_config = prefix.join("bin/llvm-config");
+    if llvm_config.is_file() {
+        vars.push(("LLVM_CONFIG_PATH".to_string(), llvm_config.into()));
+    }
+    if let Ok(version) = build.version() {
+        vars.push((
+            format!("LLVM_SYS_{}{}_PREFIX", version.major, version.minor),
+            prefix.into(),
+        ));
+    }
+    Ok(vars)
+}
+
+fn prepend_path(var: &str, dir: &Path) -> Result<OsString> {
+    let mut paths = vec![dir.to_owned()];
+    if let Some(current) = env::var_os(var) {
+        paths.extend(env::split_paths(&current).filter(|p| p != dir));
+    }
+    env::join_paths(paths)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        .with(dir)
+}
+
+/// Command which runs with the environment of the build
+pub fn command(build: &Build, program: &str, args: &[String]) -> Result<Command> {
+    let mut command = Command::new(program);
+    command.args(args).envs(build_env(build)?);
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_comes_first() -> Result<()> {
+        let dir = tempfile::TempDir::new().unwrap();
+        let build = Build::from_path(dir.path());
+        let vars = build_env(&build)?;
+        let path = &vars.iter().find(|(k, _)| k == "PATH").unwrap().1;
+        assert_eq!(
+            env::split_paths(path).next().unwrap(),
+            dir.path().join("bin")
+        );
+        // No llvm-config
+        assert!(vars.iter().all(|(k, _)| !k.starts_with("LLVM_")));
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+//! Garbage collection of caches and builds
+//!
+//! `llvmenv gc` finds directories which llvmenv does not need any more:
+//!
+//! - source caches in `$XDG_CACHE_HOME/llvmenv` whose entry has been removed
+//! - build trees of entries which are already installed
+//! - downloaded sources and logs not modified for `--older-than` days
+//! - installs interrupted before completion, which still have the [INCOMPLETE_MARKER] in the prefix
+//! - builds compiled from an entry which has been removed, and which no `.llvmenv`, alias, `LLVMENV_BUILD` or entry refers to
+//!
+//! It fails if the official releases cannot be listed, e.g. offline, since their builds would look orphaned.
+//! Do not run it while `build-entry` is running, since the build in progress is regarded as incomplete.
+
+use log::info;
+use std::{
+    collections::HashSet,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    alias::Aliases,
+    build::{prune_locals, Build, LLVMENV_BUILD},
+    config::*,
+    entry::{load_local_entries, official_releases},
+    error::*,
+    manifest::{Manifest, INCOMPLETE_MARKER},
+};
+
+/// Directories in the cache dir which are not sources of entries
+const CACHE_SUBDIRS: [&str; 3] = ["logs", "runtimes", "test-results"];
+
+/// Reason why a directory is garbage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    /// Source cache or runtime work dir whose entry or build does not exist
+    OrphanedCache,
+    /// Build tree of an installed entry
+    BuildTree,
+    /// Downloaded source not modified recently
+    StaleSource,
+    /// Logs of an old `build-entry` run
+    StaleLog,
+    /// Install interrupted before completion
+    IncompleteInstall,
+    /// Build whose entry has been removed and nothing refers to
+    OrphanedBuild,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Kind::OrphanedCache => "orphaned cache",
+            Kind::BuildTree => "build tree",
+            Kind::StaleSource => "old source",
+            Kind::StaleLog => "old log",
+            Kind::IncompleteInstall => "incomplete install",
+            Kind::OrphanedBuild => "orphaned build",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Directory to be removed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Garbage {
+    pub kind: Kind,
+    pub path: PathBuf,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// Find garbage. Sources and logs are regarded as stale if they are older than `older_than`.
+pub fn find(older_than: Duration) -> Result<Vec<Garbage>> {
+    let official = official_releases();
+    if official.is_empty() {
+        return Err(Error::OfficialReleasesUnavailable);
+    }
+    let mut entries = load_local_entries()?;
+    entries.extend(official);
+    let names: HashSet<&str> = entries.iter().map(|e| e.name()).collect();
+    // Builds referred by entries, aliases or LLVMENV_BUILD
+    let mut referred = HashSet::new();
+    for entry in &entries {
+        if let Some(build) = entry.host_compiler()? {
+            referred.insert(build.name().to_string());
+        }
+    }
+    let aliases = Aliases::load()?;
+    for alias in aliases.aliases.keys() {
+        if let Ok(name) = aliases.resolve(alias) {
+            referred.insert(name);
+        }
+    }
+    if let Some(name) = env::var(LLVMENV_BUILD).ok().filter(|name| !name.is_empty()) {
+        referred.insert(aliases.resolve(&name).unwrap_or(name));
+    }
+    let mut paths = Vec::new();
+
+    // Installed builds
+    let data = data_dir()?;
+    let mut installed = HashSet::new();
+    for prefix in sub_dirs(&data)? {
+        if prefix.join(INCOMPLETE_MARKER).exists() {
+            paths.push((Kind::IncompleteInstall, prefix));
+            continue;
+        }
+        let build = Build::from_path(&prefix);
+        let manifest = Manifest::load(&prefix)?.unwrap_or_default();
+        // Builds installed from archives or without the entry in the manifest are never orphaned
+        let compiled = manifest.entry.is_some() && manifest.archive.is_none();
+        let entry = manifest.entry.unwrap_or_else(|| build.name().to_string());
+        if names.contains(entry.as_str()) {
+            installed.insert(entry);
+        } else if compiled && !referred.contains(build.name()) && build.references()?.is_empty() {
+            paths.push((Kind::OrphanedBuild, prefix));
+        }
+    }
+
+    // Build trees of installed entries
+    for entry in entries.iter().filter(|e| installed.contains(e.name())) {
+        for stage in entry.stages()? {
+            if stage.build_dir.exists() {
+                paths.push((Kind::BuildTree, stage.build_dir));
+            }
+        }
+    }
+
+    // Sources
+    let cache = cache_dir()?;
+    for dir in sub_dirs(&cache)? {
+        let name = file_name(&dir);
+        if CACHE_SUBDIRS.contains(&name.as_str()) {
+            continue;
+        }
+        if !names.contains(name.as_str()) {
+            paths.push((Kind::OrphanedCache, dir));
+        } else if is_older(&dir, older_than) {
+            paths.push((Kind::StaleSource, dir));
+        }
+    }
+    for dir in sub_dirs(&cache.join("runtimes"))? {
+        if !data.join(file_name(&dir)).is_dir() {
+            paths.push((Kind::OrphanedCache, dir));
+        }
+    }
+    for entry in sub_dirs(&cache.join("logs"))? {
+        for run in sub_dirs(&entry)? {
+            if is_older(&run, older_than) {
+                paths.push((Kind::StaleLog, run));
+            }
+        }
+    }
+
+    // Build trees are in the sources, and should not be counted twice
+    let removed: Vec<PathBuf> = paths
+        .iter()
+        .filter(|(kind, _)| *kind != Kind::BuildTree)
+        .map(|(_, path)| path.clone())
+        .collect();
+    paths.retain(|(kind, path)| {
+        *kind != Kind::BuildTree || !removed.iter().any(|dir| path.starts_with(dir))
+    });
+
+    Ok(paths
+        .into_iter()
+        .map(|(kind, path)| Garbage {
+            kind,
+            size: dir_size(&path),
+            path,
+        })
+        .collect())
+}
+
+/// Remove the garbage, and returns the reclaimed bytes.
+/// Removed `.llvmenv` files are also forgotten from the registry of local settings.
+pub fn remove(garbage: &[Garbage]) -> Result<u64> {
+    prune_locals()?;
+    let mut total = 0;
+    for g in garbage {
+        if g.path.exists() {
+            info!(
+                "Remove {} ({}): {}",
+                g.kind,
+                format_size(g.size),
+                g.path.display()
+            );
+            fs::remove_dir_all(&g.path).with(&g.path)?;
+            total += g.size;
+        }
+    }
+    Ok(total)
+}
+
+fn sub_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir).with(dir)? {
+        let path = entry.with(dir)?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Whether nothing in the directory has been modified for `age`.
+/// The top directory alone is not enough, since its mtime does not change when files in it are modified.
+fn is_older(path: &Path, age: Duration) -> bool {
+    !modified_within(path, age)
+}
+
+fn modified_within(path: &Path, age: Duration) -> bool {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return false,
+    };
+    let recent = meta
+        .modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_none_or(|elapsed| elapsed <= age);
+    recent
+        || (meta.is_dir()
+            && fs::read_dir(path)
+                .map(|entries| entries.flatten().any(|e| modified_within(&e.path(), age)))
+                .unwrap_or(false))
+}
+
+/// Total size of files in the directory. Symbolic links are not followed.
+pub fn dir_size(path: &Path) -> u64 {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return 0,
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|e| dir_size(&e.path())).sum())
+        .unwrap_or(0)
+}
+
+/// Human readable size
+///
+/// ```
+/// use llvmenv::gc::format_size;
+/// assert_eq!(format_size(512), "512 B");
+/// assert_eq!(format_size(1536), "1.5 KiB");
+/// assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return if unit == "B" {
+                format!("{} B", bytes)
+            } else {
+                format!("{:.1} {}", size, unit)
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} TiB", size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_without_symlinks() -> std::io::Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("a"), [0u8; 100])?;
+        fs::write(dir.path().join("sub/b"), [0u8; 20])?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("a"), dir.path().join("sub/link"))?;
+        let size = dir_size(dir.path());
+        assert!((120..200).contains(&size), "{}", size);
+        Ok(())
+    }
+
+    #[test]
+    fn recently_modified_file_keeps_tree() -> std::io::Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        fs::create_dir_all(dir.path().join("sub/deep"))?;
+        let old = SystemTime::now() - Duration::from_secs(100 * 24 * 3600);
+        for path in [
+            dir.path().join("sub/deep"),
+            dir.path().join("sub"),
+            dir.path().to_owned(),
+        ] {
+            fs::File::open(&path)?.set_modified(old)?;
+        }
+        let age = Duration::from_secs(30 * 24 * 3600);
+        assert!(is_older(dir.path(), age));
+        fs::write(dir.path().join("sub/deep/file"), "")?;
+        fs::File::open(dir.path().join("sub/deep"))?.set_modified(old)?;
+        assert!(!is_older(dir.path(), age));
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Install builds which `.llvmenv` names but are missing
+//!
+//! By default, a `.llvmenv` naming a build which is not installed is skipped with a warning.
+//! The `auto_install` table in `$XDG_CONFIG_HOME/llvmenv/config.toml` changes it:
+//!
+//! ```toml
+//! [auto_install]
+//! mode = "prompt"  # "prompt", "build", "binary" or "error"
+//! binary_url = "https://example.com/llvmenv"  # or a directory
+//! ```
+//!
+//! - `prompt` asks whether to install it if the terminal is interactive
+//! - `build` installs it without asking
+//! - `binary` installs it only from an archive
+//! - `error` fails instead of falling back to another build
+//!
+//! Archives are `{binary_url}/{name}.tar.xz` made by `llvmenv archive`, and are preferred to building the entry.
+//! `llvmenv install-missing` installs the missing build of the current directory regardless of the mode.
+//! Shims and a missing `LLVMENV_BUILD` are never handled by the mode.
+
+use log::{error, info, warn};
+use serde_derive::Deserialize;
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    build::*,
+    config::*,
+    doctor,
+    entry::load_entry,
+    error::*,
+    jobs::Parallelism,
+    logs::BuildLog,
+    manifest::{now, Manifest},
+    resource::Resource,
+    shim, verify,
+};
+
+/// How to handle a missing build
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallMode {
+    Prompt,
+    Build,
+    Binary,
+    Error,
+}
+
+/// `auto_install` table of config.toml
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AutoInstallConfig {
+    /// `None` skips missing builds with a warning
+    pub mode: Option<InstallMode>,
+    /// URL or directory of archives made by `llvmenv archive`
+    pub binary_url: Option<String>,
+}
+
+impl AutoInstallConfig {
+    /// Archive of the build, `None` if `binary_url` is not set
+    ///
+    /// ```
+    /// use llvmenv::install::AutoInstallConfig;
+    /// let config = AutoInstallConfig {
+    ///     binary_url: Some("https://example.com/llvmenv/".into()),
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(config.archive("17.0.6").unwrap(), "https://example.com/llvmenv/17.0.6.tar.xz");
+    /// ```
+    pub fn archive(&self, name: &str) -> Option<String> {
+        self.binary_url
+            .as_ref()
+            .map(|url| format!("{}/{}.tar.xz", url.trim_end_matches('/'), name))
+    }
+}
+
+/// Handle the missing build named in `setting` by the `auto_install` config.
+/// Returns true if it has been installed.
+pub fn on_missing(name: &str, setting: &str) -> Result<bool> {
+    let config = load_config()?.auto_install;
+    match decide(config.mode, name, setting, ask)? {
+        Some(binary_only) => {
+            install(name, &config, binary_only)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Whether to install the missing build by the mode: `Some(binary_only)` to install it, `None` to skip it
+fn decide(
+    mode: Option<InstallMode>,
+    name: &str,
+    setting: &str,
+    ask: impl FnOnce(&str) -> Result<bool>,
+) -> Result<Option<bool>> {
+    Ok(match mode {
+        None => None,
+        Some(InstallMode::Error) => {
+            return Err(Error::BuildNotFound {
+                name: name.into(),
+                setting: setting.into(),
+            })
+        }
+        Some(InstallMode::Prompt) => {
+            if !ask(&format!(
+                "{} names {}, which is not installed. Install it?",
+                setting, name
+            ))? {
+                return Ok(None);
+            }
+            Some(false)
+        }
+        Some(InstallMode::Build) => Some(false),
+        Some(InstallMode::Binary) => Some(true),
+    })
+}
+
+/// Ask yes or no on the terminal. Always no if it is not interactive.
+fn ask(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush().with("stderr")?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).with("stdin")?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Install the build from its archive if available, or by building the entry of the same name
+pub fn install(name: &str, config: &AutoInstallConfig, binary_only: bool) -> Result<Build> {
+    if let Some(archive) = config.archive(name) {
+        match install_binary(name, &archive) {
+            Ok(build) => return Ok(build),
+            Err(e) if !binary_only => warn!("Cannot install {} from {}: {}", name, archive, e),
+            Err(e) => return Err(e),
+        }
+    } else if binary_only {
+        return Err(Error::BinaryNotAvailable {
+            name: name.into(),
+            url: "(binary_url is not set)".into(),
+        });
+    }
+
+    let entry = load_entry(name)?;
+    let checks = doctor::preflight(&entry)?;
+    for check in &checks {
+        match check.status {
+            doctor::Status::Ok => {}
+            doctor::Status::Warning => warn!("{}", check),
+            doctor::Status::Error => error!("{}", check),
+        }
+    }
+    if checks.iter().any(|c| c.status == doctor::Status::Error) {
+        return Err(Error::PreflightFailed { name: name.into() });
+    }
+    let log = BuildLog::create(entry.name())?;
+    let jobs = Parallelism::detect(entry.build_type(), &load_config()?.jobs, None);
+    // The source is kept after uninstall or build-entry without --discard
+    let source = entry.source_root()?;
+    if source.exists() {
+        info!("Use the existing source directory: {}", source.display());
+    } else {
+        entry.checkout(Some(&log))?;
+    }
+    entry.build(&jobs, false, Some(&log))?;
+    let build = Build::from_path(&entry.prefix()?);
+    let report = verify::verify_build(&build)?;
+    if !report.passed() {
+        warn!(
+            "Smoke tests of {} failed, see `llvmenv verify {}`",
+            name, name
+        );
+    }
+    shim::rehash_if_enabled()?;
+    Ok(build)
+}
+
+fn install_binary(name: &str, archive: &str) -> Result<Build> {
+    info!("Install {} from {}", name, archive);
+    let prefix = data_dir()?.join(name);
+    // Do not leave the partial prefix regarded as installed
+    let cleanup = |e: Error| -> Result<Build> {
+        if prefix.exists() {
+            std::fs::remove_dir_all(&prefix).with(&prefix)?;
+        }
+        Err(e)
+    };
+    if archive.starts_with("http://") || archive.starts_with("https://") {
+        let result = Resource::Tar {
+            url: archive.into(),
+        }
+        .download(&prefix, None);
+        if let Err(e) = result {
+            return cleanup(e);
+        }
+    } else {
+        let path = shellexpand::full(archive).map_err(|e| Error::BinaryNotAvailable {
+            name: name.into(),
+            url: format!("{} ({})", archive, e),
+        })?;
+        let path = PathBuf::from(path.to_string());
+        if !path.is_file() {
+            return Err(Error::BinaryNotAvailable {
+                name: name.into(),
+                url: archive.into(),
+            });
+        }
+        if let Err(e) = expand(&path, false) {
+            return cleanup(e);
+        }
+    }
+    let build = Build::from_path(&prefix);
+    if !build.exists() {
+        return Err(Error::BinaryNotAvailable {
+            name: name.into(),
+            url: archive.into(),
+        });
+    }
+    let mut manifest = Manifest::load(build.prefix())?.unwrap_or_default();
+    manifest.archive = Some(archive.into());
+    manifest.installed_at = Some(now());
+    manifest.save(build.prefix())?;
+    shim::rehash_if_enabled()?;
+    Ok(build)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decide_without_prompt(mode: Option<InstallMode>) -> Result<Option<bool>> {
+        decide(mode, "17.0.6", ".llvmenv", |_| panic!("must not ask"))
+    }
+
+    #[test]
+    fn install_by_mode() {
+        assert_eq!(decide_without_prompt(None).unwrap(), None);
+        assert_eq!(
+            decide_without_prompt(Some(InstallMode::Build)).unwrap(),
+            Some(false)
+        );
+        assert_eq!(
+            decide_without_prompt(Some(InstallMode::Binary)).unwrap(),
+            Some(true)
+        );
+        assert!(matches!(
+            decide_without_prompt(Some(InstallMode::Error)),
+            Err(Error::BuildNotFound { .. })
+        ));
+        let answer = |yes: bool| {
+            decide(Some(InstallMode::Prompt), "17.0.6", ".llvmenv", move |_| {
+                Ok(yes)
+            })
+        };
+        assert_eq!(answer(true).unwrap(), Some(false));
+        assert_eq!(answer(false).unwrap(), None);
+    }
+
+    #[test]
+    fn binary_without_archive() {
+        let _dirs = isolate_dirs();
+        let config = AutoInstallConfig::default();
+        assert!(matches!(
+            install("17.0.6", &config, true),
+            Err(Error::BinaryNotAvailable { .. })
+        ));
+        assert!(matches!(
+            install_binary("17.0.6", "$LLVMENV_UNDEFINED_VARIABLE/17.0.6.tar.xz"),
+            Err(Error::BinaryNotAvailable { .. })
+        ));
+    }
+
+    #[test]
+    fn remove_prefix_of_broken_archive() -> Result<()> {
+        let _dirs = isolate_dirs();
+        let name = "llvmenv-test-broken";
+        let mut archive = tar::Builder::new(Vec::new());
+        let data = vec![0u8; 100_000];
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        archive
+            .append_data(&mut header, format!("{}/bin/llvm-config", name), &data[..])
+            .with(name)?;
+        let mut bytes = archive.into_inner().with(name)?;
+        // Truncated in the middle of the file
+        bytes.truncate(30_000);
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(format!("{}.tar.xz", name));
+        std::fs::write(&path, bytes).with(&path)?;
+
+        assert!(install_binary(name, path.to_str().unwrap()).is_err());
+        assert!(!data_dir()?.join(name).exists());
+        Ok(())
+    }
+}
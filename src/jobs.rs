@@ -0,0 +1,173 @@
+//! Decide the number of parallel compile and link jobs from the available memory
+//!
+//! Using all CPUs for `-j` easily runs out of memory while linking LLVM, especially in debug builds.
+//! llvmenv estimates the memory used by one compile job and one link job for each build type,
+//! and chooses the parallelism which fits into `MemAvailable` of `/proc/meminfo`.
+//! The estimates can be overwritten in `$XDG_CONFIG_HOME/llvmenv/config.toml`:
+//!
+//! ```toml
+//! [jobs]
+//! compile_memory = 2048  # MiB per compile job
+//! link_memory = 12288    # MiB per link job
+//! ```
+//!
+//! With the Ninja generator, the link parallelism is passed to cmake as `LLVM_PARALLEL_LINK_JOBS`
+//! and `LLVM_PARALLEL_COMPILE_JOBS`.
+
+use log::info;
+use serde_derive::Deserialize;
+use std::fs;
+
+use crate::entry::BuildType;
+
+/// `[jobs]` table of config.toml
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct JobsConfig {
+    /// Memory budget of one compile job in MiB
+    pub compile_memory: Option<u64>,
+    /// Memory budget of one link job in MiB
+    pub link_memory: Option<u64>,
+}
+
+impl JobsConfig {
+    /// Memory budget of one compile job in MiB
+    pub fn compile_memory(&self, build_type: BuildType) -> u64 {
+        self.compile_memory.unwrap_or(match build_type {
+            BuildType::Debug | BuildType::RelWithDebInfo => 1536,
+            BuildType::Release | BuildType::MinSizeRel => 1024,
+        })
+    }
+
+    /// Memory budget of one link job in MiB
+    pub fn link_memory(&self, build_type: BuildType) -> u64 {
+        self.link_memory.unwrap_or(match build_type {
+            BuildType::Debug => 8192,
+            BuildType::RelWithDebInfo => 6144,
+            BuildType::Release | BuildType::MinSizeRel => 2048,
+        })
+    }
+}
+
+/// Number of parallel jobs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parallelism {
+    /// Compile jobs, passed to the generator as `-j`
+    pub compile: usize,
+    /// Link jobs, used only with the Ninja generator
+    pub link: usize,
+}
+
+impl Parallelism {
+    /// Decide parallelism from the CPUs and available memory of this machine
+    ///
+    /// `nproc` is the user specified number of compile jobs (`-j` option), which is respected
+    /// even if it does not fit into the memory.
+    pub fn detect(build_type: BuildType, config: &JobsConfig, nproc: Option<usize>) -> Self {
+        let memory = available_memory();
+        let jobs = Self::from_memory(num_cpus::get(), memory, build_type, config, nproc);
+        match memory {
+            Some(memory) => info!(
+                "Use {} compile jobs and {} link jobs for {:?} build ({} MiB memory available)",
+                jobs.compile, jobs.link, build_type, memory
+            ),
+            None => info!(
+                "Use {} compile jobs and {} link jobs (available memory is unknown)",
+                jobs.compile, jobs.link
+            ),
+        }
+        jobs
+    }
+
+    /// Same as [detect](#method.detect) without logging, e.g. for `build-entry --dry-run`
+    pub fn estimate(build_type: BuildType, config: &JobsConfig, nproc: Option<usize>) -> Self {
+        Self::from_memory(
+            num_cpus::get(),
+            available_memory(),
+            build_type,
+            config,
+            nproc,
+        )
+    }
+
+    /// Heuristics of [detect](#method.detect)
+    ///
+    /// ```
+    /// use llvmenv::{entry::BuildType, jobs::*};
+    /// let config = JobsConfig::default();
+    /// // 16 cores and 32GB memory
+    /// let jobs = Parallelism::from_memory(16, Some(32 * 1024), BuildType::Debug, &config, None);
+    /// assert_eq!(jobs, Parallelism { compile: 16, link: 4 });
+    /// let jobs = Parallelism::from_memory(16, Some(32 * 1024), BuildType::Release, &config, None);
+    /// assert_eq!(jobs, Parallelism { compile: 16, link: 16 });
+    /// // -j is respected
+    /// let jobs = Parallelism::from_memory(16, Some(32 * 1024), BuildType::Debug, &config, Some(2));
+    /// assert_eq!(jobs, Parallelism { compile: 2, link: 2 });
+    /// // Unknown memory
+    /// let jobs = Parallelism::from_memory(8, None, BuildType::Debug, &config, None);
+    /// assert_eq!(jobs, Parallelism { compile: 8, link: 8 });
+    /// ```
+    pub fn from_memory(
+        cpus: usize,
+        memory: Option<u64>,
+        build_type: BuildType,
+        config: &JobsConfig,
+        nproc: Option<usize>,
+    ) -> Self {
+        let fit = |budget: u64| -> usize {
+            match memory {
+                Some(memory) => ((memory / budget.max(1)) as usize).max(1),
+                None => usize::MAX,
+            }
+        };
+        let compile = nproc.unwrap_or_else(|| cpus.min(fit(config.compile_memory(build_type))));
+        let compile = compile.max(1);
+        let link = compile.min(fit(config.link_memory(build_type)));
+        Parallelism { compile, link }
+    }
+}
+
+/// Available memory in MiB read from `/proc/meminfo`
+pub fn available_memory() -> Option<u64> {
+    parse_meminfo(&fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+/// `MemAvailable` (or `MemTotal` for old kernels) in MiB
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let field = |name: &str| -> Option<u64> {
+        meminfo.lines().find_map(|line| {
+            let value = line.strip_prefix(name)?.strip_prefix(':')?;
+            let kib: u64 = value.trim().trim_end_matches("kB").trim().parse().ok()?;
+            Some(kib / 1024)
+        })
+    };
+    field("MemAvailable").or_else(|| field("MemTotal"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meminfo() {
+        let meminfo = "MemTotal:       32780412 kB\nMemFree:         1024000 kB\nMemAvailable:   16390206 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(16006));
+        assert_eq!(parse_meminfo("MemTotal:       2097152 kB\n"), Some(2048));
+        assert_eq!(parse_meminfo(""), None);
+    }
+
+    #[test]
+    fn config_overwrites_budget() {
+        let config = JobsConfig {
+            compile_memory: Some(4096),
+            link_memory: Some(16384),
+        };
+        let jobs = Parallelism::from_memory(16, Some(32768), BuildType::Release, &config, None);
+        assert_eq!(
+            jobs,
+            Parallelism {
+                compile: 8,
+                link: 2
+            }
+        );
+    }
+}
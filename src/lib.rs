@@ -1,5 +1,23 @@
+pub mod alias;
 pub mod build;
 pub mod config;
+pub mod cross;
+pub mod diagnose;
+pub mod doctor;
 pub mod entry;
 pub mod error;
+pub mod exec;
+pub mod gc;
+pub mod install;
+pub mod jobs;
+pub mod link;
+pub mod lit;
+pub mod logs;
+pub mod manifest;
+pub mod plan;
 pub mod resource;
+pub mod runtime;
+pub mod selector;
+pub mod shim;
+pub mod stage;
+pub mod verify;
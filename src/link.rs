@@ -0,0 +1,164 @@
+//! Toolchains installed outside of llvmenv
+//!
+//! `llvmenv link {name} {prefix}` registers an existing LLVM installation, e.g. `/opt/llvm-15`
+//! or `/usr/lib/llvm-14`, into `$XDG_CONFIG_HOME/llvmenv/links.toml`:
+//!
+//! ```toml
+//! [links]
+//! llvm-14 = "/usr/lib/llvm-14"
+//! ```
+//!
+//! Linked builds can be used by `global`, `local` and `prefix` like builds of llvmenv,
+//! but they are never copied nor removed by llvmenv.
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{alias::Aliases, build::Build, config::*, error::*};
+
+pub const LINKS_TOML: &str = "links.toml";
+
+/// Registry of linked builds, keyed by name
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Links {
+    #[serde(default)]
+    pub links: BTreeMap<String, PathBuf>,
+}
+
+impl Links {
+    pub fn load() -> Result<Self> {
+        let path = config_dir()?.join(LINKS_TOML);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(&path).with(&path)?)?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = config_dir()?.join(LINKS_TOML);
+        fs::write(&path, toml::to_string_pretty(self)?).with(&path)
+    }
+}
+
+/// Register the prefix as a build. `llvm-config --version` of the prefix has to succeed.
+pub fn link(name: &str, prefix: &Path) -> Result<Build> {
+    let invalid = |message: &str| Error::InvalidLink {
+        name: name.into(),
+        message: message.into(),
+    };
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return Err(invalid(
+            "Name must not be empty nor contain path separators",
+        ));
+    }
+    // These names are resolved into other builds before links
+    if name == "system" || name.starts_with("system-") {
+        return Err(invalid(
+            "Names starting with system- are reserved for system builds",
+        ));
+    }
+    if Aliases::load()?.aliases.contains_key(name) {
+        return Err(invalid("Alias of the same name already exists"));
+    }
+    if data_dir()?.join(name).exists() {
+        return Err(invalid("Build of the same name already exists"));
+    }
+    let prefix = prefix.canonicalize().with(prefix)?;
+    let build = Build::linked(name, &prefix);
+    let version = build
+        .version()
+        .map_err(|e| invalid(&format!("llvm-config does not work: {}", e)))?;
+    let mut links = Links::load()?;
+    links.links.insert(name.into(), prefix.clone());
+    links.save()?;
+    info!("Link LLVM {} in {} as {}", version, prefix.display(), name);
+    Ok(build)
+}
+
+/// Remove the build from the registry. The prefix itself is kept.
+///
+/// A build which global or local settings point at is not unlinked unless `force`.
+pub fn unlink(name: &str, force: bool) -> Result<()> {
+    let mut links = Links::load()?;
+    let prefix = match links.links.get(name) {
+        Some(prefix) => prefix,
+        None => {
+            return Err(Error::InvalidLink {
+                name: name.into(),
+                message: "Not linked".into(),
+            })
+        }
+    };
+    let references = Build::linked(name, prefix).references()?;
+    if !references.is_empty() {
+        if !force {
+            return Err(Error::BuildInUse {
+                name: name.into(),
+                paths: references,
+            });
+        }
+        for path in &references {
+            warn!("{} points at the unlinked build", path.display());
+        }
+    }
+    links.links.remove(name);
+    links.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(name: &str, prefix: &Path) -> bool {
+        matches!(link(name, prefix), Err(Error::InvalidLink { .. }))
+    }
+
+    #[test]
+    fn link_validation() -> Result<()> {
+        let _dirs = isolate_dirs();
+        let prefix = tempfile::TempDir::new().unwrap();
+        assert!(rejected("", prefix.path()));
+        assert!(rejected("llvm/14", prefix.path()));
+        assert!(rejected("system", prefix.path()));
+        assert!(rejected("system-14", prefix.path()));
+
+        let mut aliases = Aliases::load()?;
+        aliases
+            .aliases
+            .insert("llvmenv-test-alias".into(), "system".into());
+        aliases.save()?;
+        assert!(rejected("llvmenv-test-alias", prefix.path()));
+
+        let installed = data_dir()?.join("llvmenv-test-installed");
+        fs::create_dir_all(&installed).with(&installed)?;
+        assert!(rejected("llvmenv-test-installed", prefix.path()));
+
+        // No llvm-config in the prefix
+        assert!(rejected("llvmenv-test-link", prefix.path()));
+        assert!(!Links::load()?.links.contains_key("llvmenv-test-link"));
+        Ok(())
+    }
+
+    #[test]
+    fn refuse_unlink_build_in_use() -> Result<()> {
+        let _dirs = isolate_dirs();
+        let name = "llvmenv-test-linked";
+        let prefix = tempfile::TempDir::new().unwrap();
+        let mut links = Links::load()?;
+        links.links.insert(name.into(), prefix.path().into());
+        links.save()?;
+        let project = tempfile::TempDir::new().unwrap();
+        Build::from_name(name)?.set_local(project.path())?;
+
+        assert!(matches!(unlink(name, false), Err(Error::BuildInUse { .. })));
+        assert!(Links::load()?.links.contains_key(name));
+        unlink(name, true)?;
+        assert!(!Links::load()?.links.contains_key(name));
+        Ok(())
+    }
+}
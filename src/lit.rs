@@ -0,0 +1,245 @@
+//! Run LLVM's own test suites (lit-based `check-*` targets) in the build dir of an entry
+//!
+//! `llvmenv test {entry} --suite check-llvm --suite check-clang` builds the check targets
+//! through the configured generator, streams their output, and summarises the lit results.
+//! The latest result of each suite is kept in `$XDG_CACHE_HOME/llvmenv/test-results/{entry}.toml`.
+
+use log::{info, warn};
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, fs, path::PathBuf};
+
+use crate::{config::*, entry::Entry, error::*, jobs::Parallelism, logs::BuildLog, manifest::now};
+
+/// Number of tests in each result category of lit
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LitSummary {
+    pub passed: u64,
+    pub failed: u64,
+    /// Expectedly failed (XFAIL)
+    pub xfail: u64,
+    /// Unexpectedly passed (XPASS)
+    pub xpass: u64,
+    pub unsupported: u64,
+    pub skipped: u64,
+    pub unresolved: u64,
+    pub timed_out: u64,
+}
+
+impl LitSummary {
+    /// Whether there is no unexpected result
+    pub fn ok(&self) -> bool {
+        self.failed == 0 && self.xpass == 0 && self.unresolved == 0 && self.timed_out == 0
+    }
+
+    pub fn total(&self) -> u64 {
+        self.passed
+            + self.failed
+            + self.xfail
+            + self.xpass
+            + self.unsupported
+            + self.skipped
+            + self.unresolved
+            + self.timed_out
+    }
+}
+
+impl std::ops::AddAssign for LitSummary {
+    fn add_assign(&mut self, other: Self) {
+        self.passed += other.passed;
+        self.failed += other.failed;
+        self.xfail += other.xfail;
+        self.xpass += other.xpass;
+        self.unsupported += other.unsupported;
+        self.skipped += other.skipped;
+        self.unresolved += other.unresolved;
+        self.timed_out += other.timed_out;
+    }
+}
+
+impl fmt::Display for LitSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed, {} xfail, {} xpass, {} unsupported",
+            self.passed, self.failed, self.xfail, self.xpass, self.unsupported
+        )?;
+        for (n, label) in [
+            (self.skipped, "skipped"),
+            (self.unresolved, "unresolved"),
+            (self.timed_out, "timed out"),
+        ] {
+            if n > 0 {
+                write!(f, ", {} {}", n, label)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse the summary printed at the end of lit
+///
+/// Both the current format (`Passed : 123 (95.00%)`) and the format before LLVM 12
+/// (`Expected Passes : 123`) are supported. Summaries of several lit runs in one output are summed.
+///
+/// ```
+/// use llvmenv::lit::parse_summary;
+/// let output = r#"
+/// Total Discovered Tests: 120
+///   Unsupported      :   10 (8.33%)
+///   Passed           :  105 (87.50%)
+///   Expectedly Failed:    3 (2.50%)
+///   Failed           :    2 (1.67%)
+/// "#;
+/// let summary = parse_summary(output);
+/// assert_eq!(summary.passed, 105);
+/// assert_eq!(summary.failed, 2);
+/// assert_eq!(summary.xfail, 3);
+/// assert_eq!(summary.unsupported, 10);
+/// assert!(!summary.ok());
+/// ```
+pub fn parse_summary(output: &str) -> LitSummary {
+    let re = Regex::new(r"^\s*([A-Za-z ]+?)\s*:\s*(\d+)(\s+\(|\s*$)").unwrap();
+    let mut summary = LitSummary::default();
+    for line in output.lines() {
+        let cap = match re.captures(line) {
+            Some(cap) => cap,
+            None => continue,
+        };
+        let n: u64 = cap[2].parse().unwrap_or(0);
+        let field = match &cap[1] {
+            "Passed" | "Expected Passes" => &mut summary.passed,
+            "Failed" | "Unexpected Failures" => &mut summary.failed,
+            "Expectedly Failed" | "Expected Failures" => &mut summary.xfail,
+            "Unexpectedly Passed" | "Unexpected Passes" => &mut summary.xpass,
+            "Unsupported" | "Unsupported Tests" => &mut summary.unsupported,
+            "Skipped" | "Excluded" => &mut summary.skipped,
+            "Unresolved" | "Unresolved Tests" => &mut summary.unresolved,
+            "Timed Out" | "Individual Timeouts" => &mut summary.timed_out,
+            _ => continue,
+        };
+        *field += n;
+    }
+    summary
+}
+
+/// Result of one suite
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SuiteResult {
+    pub summary: LitSummary,
+    /// Whether the check target succeeded
+    pub succeeded: bool,
+    /// RFC 3339 timestamp when the suite finished
+    pub finished_at: String,
+    /// Log file of the run
+    pub log: PathBuf,
+}
+
+/// Test results of a build, keyed by suite name
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct TestResults {
+    #[serde(default)]
+    pub suites: BTreeMap<String, SuiteResult>,
+}
+
+impl TestResults {
+    pub fn path(entry: &str) -> Result<PathBuf> {
+        Ok(cache_dir()?
+            .join("test-results")
+            .join(format!("{}.toml", entry)))
+    }
+
+    pub fn load(entry: &str) -> Result<Self> {
+        let path = Self::path(entry)?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(&path).with(&path)?)?)
+    }
+
+    pub fn save(&self, entry: &str) -> Result<()> {
+        let path = Self::path(entry)?;
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir).with(dir)?;
+        fs::write(&path, toml::to_string_pretty(self)?).with(&path)
+    }
+}
+
+/// Run the check targets in the build dir of the entry
+///
+/// Every suite is run even if some of them fail, and their results are saved.
+pub fn run_suites(entry: &Entry, suites: &[String], jobs: &Parallelism) -> Result<TestResults> {
+    let build_dir = entry.build_path()?;
+    if !build_dir.join("CMakeCache.txt").exists() {
+        return Err(Error::BuildDirNotFound {
+            name: entry.name().into(),
+            path: build_dir,
+        });
+    }
+    let log = BuildLog::create(entry.name())?;
+    let mut results = TestResults::load(entry.name())?;
+    for suite in suites {
+        info!("Run {} in {}", suite, build_dir.display());
+        let log_path = log.phase(suite);
+        let status = entry
+            .build_command(jobs, Some(suite))?
+            .check_run_logged(&log_path);
+        if let Err(e) = &status {
+            warn!("{} failed: {}", suite, e);
+        }
+        let output = fs::read(&log_path).with(&log_path)?;
+        let summary = parse_summary(&String::from_utf8_lossy(&output));
+        results.suites.insert(
+            suite.clone(),
+            SuiteResult {
+                summary,
+                succeeded: status.is_ok(),
+                finished_at: now(),
+                log: log_path,
+            },
+        );
+        results.save(entry.name())?;
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_format() {
+        let output = "Testing Time: 123.45s\n  Expected Passes    : 1234\n  Expected Failures  : 12\n  Unsupported Tests  : 34\n  Unexpected Failures: 2\n";
+        let summary = parse_summary(output);
+        assert_eq!(
+            summary,
+            LitSummary {
+                passed: 1234,
+                failed: 2,
+                xfail: 12,
+                unsupported: 34,
+                ..Default::default()
+            }
+        );
+        assert_eq!(summary.total(), 1282);
+    }
+
+    #[test]
+    fn sum_of_runs() {
+        let output = "  Passed: 10 (100.00%)\n[1/2] Running lld test suite\n  Passed: 5 (83.33%)\n  Unexpectedly Passed: 1 (16.67%)\n";
+        let summary = parse_summary(output);
+        assert_eq!(summary.passed, 15);
+        assert_eq!(summary.xpass, 1);
+        assert!(!summary.ok());
+    }
+
+    #[test]
+    fn ignore_others() {
+        assert_eq!(
+            parse_summary(
+                "-- Build files have been written to: /tmp/build\nwarning: unused variable: 3\n"
+            ),
+            LitSummary::default()
+        );
+    }
+}
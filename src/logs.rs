@@ -0,0 +1,209 @@
+//! Captured output of `llvmenv build-entry`
+//!
+//! Every run of `build-entry` creates a directory `$XDG_CACHE_HOME/llvmenv/logs/{entry}/{timestamp}`,
+//! and the output of each phase (checkout, configure, build, install) is teed into `{phase}.log` in it.
+//! Phases of intermediate bootstrap stages are prefixed by the stage, e.g. `stage1-build.log`.
+//! `llvmenv logs {entry}` shows the logs of the latest run.
+
+use log::info;
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{config::*, error::*};
+
+/// Phases of `build-entry` in execution order
+pub const PHASES: [&str; 6] = [
+    "checkout",
+    "configure",
+    "build",
+    "train",
+    "merge",
+    "install",
+];
+
+/// Log directory of one `build-entry` run
+#[derive(Debug, Clone)]
+pub struct BuildLog {
+    dir: PathBuf,
+}
+
+impl BuildLog {
+    /// Create a new log directory for the entry, named by the current time
+    pub fn create(entry: &str) -> Result<Self> {
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        let stamp = now
+            .format(format_description!(
+                "[year][month][day]-[hour][minute][second]"
+            ))
+            .expect("Invalid time format");
+        let dir = log_root(entry)?.join(stamp);
+        fs::create_dir_all(&dir).with(&dir)?;
+        info!("Logs are written into {}", dir.display());
+        Ok(BuildLog { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Log file of the phase, e.g. `configure.log`
+    pub fn phase(&self, phase: &str) -> PathBuf {
+        self.dir.join(format!("{}.log", phase))
+    }
+
+    /// Append a message into the log of the phase
+    pub fn note(&self, phase: &str, message: &str) -> Result<()> {
+        append(&self.phase(phase), message)
+    }
+}
+
+/// Append a line into a log file
+pub fn append(log: &Path, message: &str) -> Result<()> {
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .with(log)?;
+    writeln!(f, "{}", message).with(log)
+}
+
+/// Directory which holds all runs of the entry
+pub fn log_root(entry: &str) -> Result<PathBuf> {
+    Ok(cache_dir()?.join("logs").join(entry))
+}
+
+/// Log directory of the latest run of the entry
+pub fn latest(entry: &str) -> Result<Option<PathBuf>> {
+    let root = log_root(entry)?;
+    if !root.exists() {
+        return Ok(None);
+    }
+    let mut runs = Vec::new();
+    for dir in fs::read_dir(&root).with(&root)? {
+        let dir = dir.with(&root)?.path();
+        if dir.is_dir() {
+            runs.push(dir);
+        }
+    }
+    // Timestamps are sortable as strings
+    runs.sort();
+    Ok(runs.pop())
+}
+
+/// Log files of a run in execution order
+///
+/// Logs of `build-entry` are sorted by [PHASES], with the phases of bootstrap stages
+/// before the final ones. Other logs, e.g. of `llvmenv test`, follow in the order they are written.
+pub fn phase_logs(run: &Path) -> Result<Vec<PathBuf>> {
+    let mut logs = Vec::new();
+    for path in fs::read_dir(run).with(run)? {
+        let path = path.with(run)?.path();
+        if path.extension().is_some_and(|ext| ext == "log") {
+            let modified = path.metadata().and_then(|m| m.modified()).with(&path)?;
+            let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+            logs.push((phase_order(&stem), modified, path));
+        }
+    }
+    logs.sort();
+    Ok(logs.into_iter().map(|(.., path)| path).collect())
+}
+
+/// Sort key of a log name: (group, stage, phase)
+fn phase_order(name: &str) -> (u8, u32, usize) {
+    let position = |phase: &str| PHASES.iter().position(|p| *p == phase);
+    if name == "checkout" {
+        return (0, 0, 0);
+    }
+    if let Some((stage, phase)) = name.split_once('-') {
+        // Native tablegen of cross-compilation is built first
+        let n = match stage {
+            "native" => Some(0),
+            _ => stage.strip_prefix("stage").and_then(|n| n.parse().ok()),
+        };
+        if let (Some(n), Some(i)) = (n, position(phase)) {
+            return (1, n, i);
+        }
+    }
+    match position(name) {
+        Some(i) => (1, u32::MAX, i),
+        None => (2, 0, 0),
+    }
+}
+
+/// Print the log file, and keep printing what is appended to it like `tail -f`
+///
+/// If `run` is given, switch to the next log of the run when it appears,
+/// i.e. follow the phases of a running build.
+pub fn follow(log: &Path, run: Option<&Path>) -> Result<()> {
+    let mut current = log.to_owned();
+    let mut f = fs::File::open(&current).with(&current)?;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        f.read_to_end(&mut buf).with(&current)?;
+        if buf.is_empty() {
+            if let Some(run) = run {
+                let logs = phase_logs(run)?;
+                let next = logs
+                    .iter()
+                    .position(|path| path == &current)
+                    .and_then(|i| logs.get(i + 1));
+                if let Some(next) = next {
+                    current = next.clone();
+                    f = fs::File::open(&current).with(&current)?;
+                    continue;
+                }
+            }
+            thread::sleep(Duration::from_millis(500));
+            // Detect truncation
+            let len = f.metadata().with(&current)?.len();
+            if f.stream_position().with(&current)? > len {
+                f.seek(SeekFrom::Start(0)).with(&current)?;
+            }
+        } else {
+            io::stdout().write_all(&buf).with(&current)?;
+            io::stdout().flush().with(&current)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_of_phases() {
+        let mut names = vec![
+            "check-llvm",
+            "install",
+            "build",
+            "stage2-build",
+            "configure",
+            "stage1-build",
+            "stage1-configure",
+            "native-build",
+            "checkout",
+        ];
+        names.sort_by_key(|name| phase_order(name));
+        assert_eq!(
+            names,
+            [
+                "checkout",
+                "native-build",
+                "stage1-configure",
+                "stage1-build",
+                "stage2-build",
+                "configure",
+                "build",
+                "install",
+                "check-llvm"
+            ]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Record of how a build was made
+//!
+//! `llvmenv build-entry` writes `llvmenv-manifest.toml` into the install prefix of the build,
+//! and later commands, e.g. `llvmenv verify`, add their results into it.
+
+use serde_derive::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{entry::Entry, error::*, runtime::RuntimeRecord, verify::VerifyReport};
+
+pub const MANIFEST_TOML: &str = "llvmenv-manifest.toml";
+
+/// File in the prefix while installing, which is removed after the manifest is written
+pub const INCOMPLETE_MARKER: &str = ".llvmenv-incomplete";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Manifest {
+    /// Name of the entry which this build is compiled from
+    pub entry: Option<String>,
+    /// Version of the entry
+    pub version: Option<String>,
+    /// CMake build type, e.g. "Release"
+    pub build_type: Option<String>,
+    /// Number of bootstrap stages. `None` for a single stage build.
+    pub stages: Option<u32>,
+    /// `optimize` setting of the entry, e.g. "pgo+lto"
+    pub optimize: Option<String>,
+    /// Phases run by the last `build-entry`, e.g. "stage2-train". Skipped configures are not included.
+    #[serde(default)]
+    pub phases: Vec<String>,
+    /// Host triple of a cross-compiled build, which cannot run on this machine
+    pub foreign_host: Option<String>,
+    /// RFC 3339 timestamp when the build has been installed
+    pub installed_at: Option<String>,
+    /// Archive which the build has been installed from, instead of building the entry here
+    pub archive: Option<String>,
+    /// Runtimes for other targets added by `llvmenv add-runtime`
+    #[serde(default)]
+    pub runtimes: Vec<RuntimeRecord>,
+    /// Result of the latest post-install smoke tests
+    pub verify: Option<VerifyReport>,
+}
+
+impl Manifest {
+    /// Manifest for a build just installed from the entry
+    pub fn from_entry(entry: &Entry) -> Self {
+        Manifest {
+            entry: Some(entry.name().into()),
+            version: entry.version().map(|v| v.to_string()),
+            build_type: Some(format!("{:?}", entry.build_type())),
+            stages: None,
+            optimize: None,
+            phases: Vec::new(),
+            foreign_host: None,
+            installed_at: Some(now()),
+            archive: None,
+            runtimes: Vec::new(),
+            verify: None,
+        }
+    }
+
+    /// Manifest for a build re-installed from the entry into the prefix.
+    /// Runtimes added by `add-runtime` and the latest smoke test result of the previous install are kept.
+    pub fn reinstalled(entry: &Entry, prefix: &Path) -> Result<Self> {
+        let mut manifest = Self::from_entry(entry);
+        if let Some(previous) = Self::load(prefix)? {
+            manifest.runtimes = previous.runtimes;
+            manifest.verify = previous.verify;
+        }
+        Ok(manifest)
+    }
+
+    /// Load the manifest in the prefix. `None` if it does not exist, e.g. for builds by old llvmenv.
+    pub fn load(prefix: &Path) -> Result<Option<Self>> {
+        let path = prefix.join(MANIFEST_TOML);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(
+            &fs::read_to_string(&path).with(&path)?,
+        )?))
+    }
+
+    pub fn save(&self, prefix: &Path) -> Result<()> {
+        let path = prefix.join(MANIFEST_TOML);
+        fs::write(&path, toml::to_string_pretty(self)?).with(&path)
+    }
+}
+
+/// Current time in RFC 3339 format
+pub fn now() -> String {
+    OffsetDateTime::now_local()
+        .unwrap_or_else(|_| OffsetDateTime::now_utc())
+        .format(&Rfc3339)
+        .expect("Invalid time format")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_runtimes_on_reinstall() -> Result<()> {
+        let prefix = tempfile::TempDir::new().unwrap();
+        let entry = Entry::official(17, 0, 6);
+        let mut previous = Manifest::from_entry(&entry);
+        previous.runtimes.push(RuntimeRecord {
+            target: "wasm32-wasi".into(),
+            sysroot: None,
+            components: vec!["builtins".into()],
+            installed_at: now(),
+        });
+        previous.archive = Some("17.0.6.tar.xz".into());
+        previous.save(prefix.path())?;
+
+        let manifest = Manifest::reinstalled(&entry, prefix.path())?;
+        assert_eq!(manifest.runtimes, previous.runtimes);
+        assert_eq!(manifest.archive, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Describe what `llvmenv build-entry` will do without doing it
+//!
+//! A [Plan][Plan] lists every resource to be fetched, every directory to be created or removed,
+//! and the exact external commands to be executed, in the order they will happen.
+//! It is generated by [Entry::plan](../entry/enum.Entry.html#method.plan),
+//! and printed by `llvmenv build-entry --dry-run` as shell-escaped text or as JSON.
+//!
+//! [Plan]: ./struct.Plan.html
+
+use serde_derive::Serialize;
+use std::{fmt, path::PathBuf, process};
+
+use crate::error::*;
+
+/// Remote resource to be downloaded into `dest`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedResource {
+    pub url: String,
+    pub dest: PathBuf,
+}
+
+/// External command to be executed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedCommand {
+    /// Build step this command belongs to, e.g. "configure" or "build"
+    pub phase: String,
+    pub program: String,
+    pub args: Vec<String>,
+    /// Working directory. `None` means the current directory of llvmenv.
+    pub cwd: Option<PathBuf>,
+    /// Reason why this command will be skipped. `None` means it will be executed.
+    pub skip: Option<String>,
+}
+
+impl PlannedCommand {
+    pub fn new(phase: &str, cmd: &process::Command) -> Self {
+        PlannedCommand {
+            phase: phase.into(),
+            program: cmd.get_program().to_string_lossy().into(),
+            args: cmd
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into())
+                .collect(),
+            cwd: cmd.get_current_dir().map(|dir| dir.to_owned()),
+            skip: None,
+        }
+    }
+
+    /// Command line in a form which can be pasted into POSIX shells
+    pub fn to_shell(&self) -> String {
+        let mut line = String::new();
+        if let Some(cwd) = &self.cwd {
+            line.push_str(&format!("cd {} && ", shell_quote(&cwd.to_string_lossy())));
+        }
+        line.push_str(&shell_quote(&self.program));
+        for arg in &self.args {
+            line.push(' ');
+            line.push_str(&shell_quote(arg));
+        }
+        line
+    }
+}
+
+/// Every side effect of building an entry, in execution order
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Plan {
+    pub entry: String,
+    /// Directories removed before the checkout (`--discard`)
+    pub remove_before: Vec<PathBuf>,
+    /// Resources to be fetched. Empty when the source already exists or the entry is local.
+    pub resources: Vec<PlannedResource>,
+    /// Whether existing sources are updated by git/svn (`--update`)
+    pub update: bool,
+    /// Directories created for the build
+    pub create_dirs: Vec<PathBuf>,
+    pub commands: Vec<PlannedCommand>,
+    /// Directories removed after the build (`--discard`)
+    pub remove_after: Vec<PathBuf>,
+}
+
+impl Plan {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# Build plan for entry '{}'", self.entry)?;
+        for dir in &self.remove_before {
+            writeln!(f, "rm -rf {}", shell_quote(&dir.to_string_lossy()))?;
+        }
+        for res in &self.resources {
+            writeln!(f, "# fetch {}", res.url)?;
+            writeln!(f, "#    -> {}", res.dest.display())?;
+        }
+        if self.update {
+            writeln!(f, "# update sources with git pull / svn update")?;
+        }
+        for dir in &self.create_dirs {
+            writeln!(f, "mkdir -p {}", shell_quote(&dir.to_string_lossy()))?;
+        }
+        let mut phase = "";
+        for cmd in &self.commands {
+            if cmd.phase != phase {
+                phase = &cmd.phase;
+                writeln!(f, "# {}", phase)?;
+            }
+            match &cmd.skip {
+                Some(reason) => {
+                    writeln!(f, "# skipped ({}):", reason)?;
+                    writeln!(f, "# {}", cmd.to_shell())?;
+                }
+                None => writeln!(f, "{}", cmd.to_shell())?,
+            }
+        }
+        for dir in &self.remove_after {
+            writeln!(f, "rm -rf {}", shell_quote(&dir.to_string_lossy()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Quote a word for POSIX shells if it contains special characters
+///
+/// ```
+/// use llvmenv::plan::shell_quote;
+/// assert_eq!(shell_quote("-DCMAKE_BUILD_TYPE=Release"), "-DCMAKE_BUILD_TYPE=Release");
+/// assert_eq!(shell_quote("Unix Makefiles"), "'Unix Makefiles'");
+/// assert_eq!(shell_quote("X86;ARM"), "'X86;ARM'");
+/// assert_eq!(shell_quote("it's"), r"'it'\''s'");
+/// assert_eq!(shell_quote(""), "''");
+/// ```
+pub fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.into()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// Quote a word for fish, which allows `\\` and `\'` escapes in single quotes
+///
+/// ```
+/// use llvmenv::plan::fish_quote;
+/// assert_eq!(fish_quote("17.0.6"), "17.0.6");
+/// assert_eq!(fish_quote("a b; $(x)"), "'a b; $(x)'");
+/// assert_eq!(fish_quote(r"it's \"), r"'it\'s \\'");
+/// ```
+pub fn fish_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.into()
+    } else {
+        format!("'{}'", word.replace('\\', r"\\").replace('\'', r"\'"))
+    }
+}
@@ -56,61 +56,8 @@ impl Resource {
     /// assert_eq!(tar, Resource::Tar { url: tar_url.into() });
     /// ```
     pub fn from_url(url_str: &str) -> Result<Self> {
-        // Check file extension
-        if let Ok(filename) = get_filename_from_url(url_str) {
-            for ext in &[".tar.gz", ".tar.xz", ".tar.bz2", ".tar.Z", ".tgz", ".taz"] {
-                if filename.ends_with(ext) {
-                    debug!("Find archive extension '{}' at the end of URL", ext);
-                    return Ok(Resource::Tar {
-                        url: url_str.into(),
-                    });
-                }
-            }
-
-            if filename.ends_with("trunk") {
-                debug!("Find 'trunk' at the end of URL");
-                return Ok(Resource::Svn {
-                    url: url_str.into(),
-                });
-            }
-
-            if filename.ends_with(".git") {
-                debug!("Find '.git' extension");
-                return Ok(Resource::Git {
-                    url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
-                });
-            }
-        }
-
-        // Hostname
-        let url = Url::parse(url_str).map_err(|_| Error::InvalidUrl {
-            url: url_str.into(),
-        })?;
-        for service in &["github.com", "gitlab.com"] {
-            if url.host_str() == Some(service) {
-                debug!("URL is a cloud git service: {}", service);
-                return Ok(Resource::Git {
-                    url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
-                });
-            }
-        }
-
-        if url.host_str() == Some("llvm.org") {
-            if url.path().starts_with("/svn") {
-                debug!("URL is LLVM SVN repository");
-                return Ok(Resource::Svn {
-                    url: url_str.into(),
-                });
-            }
-            if url.path().starts_with("/git") {
-                debug!("URL is LLVM Git repository");
-                return Ok(Resource::Git {
-                    url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
-                });
-            }
+        if let Some(res) = Self::guess_from_url(url_str)? {
+            return Ok(res);
         }
 
         // Try access with git
@@ -158,18 +105,83 @@ impl Resource {
         }
     }
 
-    pub fn download(&self, dest: &Path) -> Result<()> {
+    /// Detect remote resource only from the URL string, without network access
+    ///
+    /// Returns `None` if it cannot be decided, and then [from_url](#method.from_url) tries git access.
+    pub fn guess_from_url(url_str: &str) -> Result<Option<Self>> {
+        // Check file extension
+        if let Ok(filename) = get_filename_from_url(url_str) {
+            for ext in &[".tar.gz", ".tar.xz", ".tar.bz2", ".tar.Z", ".tgz", ".taz"] {
+                if filename.ends_with(ext) {
+                    debug!("Find archive extension '{}' at the end of URL", ext);
+                    return Ok(Some(Resource::Tar {
+                        url: url_str.into(),
+                    }));
+                }
+            }
+
+            if filename.ends_with("trunk") {
+                debug!("Find 'trunk' at the end of URL");
+                return Ok(Some(Resource::Svn {
+                    url: url_str.into(),
+                }));
+            }
+
+            if filename.ends_with(".git") {
+                debug!("Find '.git' extension");
+                return Ok(Some(Resource::Git {
+                    url: strip_branch_from_url(url_str)?,
+                    branch: get_branch_from_url(url_str)?,
+                }));
+            }
+        }
+
+        // Hostname
+        let url = Url::parse(url_str).map_err(|_| Error::InvalidUrl {
+            url: url_str.into(),
+        })?;
+        for service in &["github.com", "gitlab.com"] {
+            if url.host_str() == Some(service) {
+                debug!("URL is a cloud git service: {}", service);
+                return Ok(Some(Resource::Git {
+                    url: strip_branch_from_url(url_str)?,
+                    branch: get_branch_from_url(url_str)?,
+                }));
+            }
+        }
+
+        if url.host_str() == Some("llvm.org") {
+            if url.path().starts_with("/svn") {
+                debug!("URL is LLVM SVN repository");
+                return Ok(Some(Resource::Svn {
+                    url: url_str.into(),
+                }));
+            }
+            if url.path().starts_with("/git") {
+                debug!("URL is LLVM Git repository");
+                return Ok(Some(Resource::Git {
+                    url: strip_branch_from_url(url_str)?,
+                    branch: get_branch_from_url(url_str)?,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Download into `dest`. Output of git/svn is also written into `log` if given.
+    pub fn download(&self, dest: &Path, log: Option<&Path>) -> Result<()> {
         if !dest.exists() {
             fs::create_dir_all(dest).with(dest)?;
         }
         if !dest.is_dir() {
-            return Err(io::Error::new(io::ErrorKind::Other, "Not a directory")).with(dest);
+            return Err(io::Error::other("Not a directory")).with(dest);
         }
         match self {
             Resource::Svn { url, .. } => Command::new("svn")
                 .args(["co", url.as_str(), "-r", "HEAD"])
                 .arg(dest)
-                .check_run()?,
+                .check_run_to(log)?,
             Resource::Git { url, branch } => {
                 info!("Git clone {}", url);
                 let mut git = Command::new("git");
@@ -178,10 +190,13 @@ impl Resource {
                 if let Some(branch) = branch {
                     git.args(["-b", branch]);
                 }
-                git.check_run()?;
+                git.check_run_to(log)?;
             }
             Resource::Tar { url } => {
                 info!("Download Tar file: {}", url);
+                if let Some(log) = log {
+                    crate::logs::append(log, &format!("Download {} into {}", url, dest.display()))?;
+                }
                 // This will be large, but at most ~100MB
                 let rt = Runtime::new()?;
                 let mut bytes = rt.block_on(download(url))?;
@@ -210,16 +225,16 @@ impl Resource {
         Ok(())
     }
 
-    pub fn update(&self, dest: &Path) -> Result<()> {
+    pub fn update(&self, dest: &Path, log: Option<&Path>) -> Result<()> {
         match self {
             Resource::Svn { .. } => Command::new("svn")
                 .arg("update")
                 .current_dir(dest)
-                .check_run()?,
+                .check_run_to(log)?,
             Resource::Git { .. } => Command::new("git")
                 .arg("pull")
                 .current_dir(dest)
-                .check_run()?,
+                .check_run_to(log)?,
             Resource::Tar { .. } => {}
         }
         Ok(())
@@ -276,7 +291,7 @@ where
         } else {
             match self.stream.next() {
                 Some(Ok(bytes)) => bytes,
-                Some(Err(err)) => return Err(io::Error::new(io::ErrorKind::Other, err)),
+                Some(Err(err)) => return Err(io::Error::other(err)),
                 None => return Ok(0),
             }
         };
@@ -295,10 +310,10 @@ fn get_filename_from_url(url_str: &str) -> Result<String> {
     let url = ::url::Url::parse(url_str).map_err(|_| Error::InvalidUrl {
         url: url_str.into(),
     })?;
-    let seg = url.path_segments().ok_or(Error::InvalidUrl {
+    let mut seg = url.path_segments().ok_or(Error::InvalidUrl {
         url: url_str.into(),
     })?;
-    let filename = seg.last().ok_or(Error::InvalidUrl {
+    let filename = seg.next_back().ok_or(Error::InvalidUrl {
         url: url_str.into(),
     })?;
     Ok(filename.to_string())
@@ -331,7 +346,7 @@ mod tests {
             branch: None,
         };
         let tmp_dir = TempDir::new().with("/tmp")?;
-        git.download(tmp_dir.path())?;
+        git.download(tmp_dir.path(), None)?;
         let cargo_toml = tmp_dir.path().join("Cargo.toml");
         assert!(cargo_toml.exists());
         Ok(())
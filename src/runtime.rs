@@ -0,0 +1,292 @@
+//! Runtimes for other targets added to an installed build
+//!
+//! An installed clang can generate code for other targets, e.g. wasm32 or arm-none-eabi,
+//! but it cannot link programs for them without compiler-rt builtins.
+//! `llvmenv add-runtime {build} --target {triple}` builds them by the clang of the build itself,
+//! from the source of the entry which the build is compiled from:
+//!
+//! - compiler-rt builtins are installed into the resource dir of clang,
+//!   i.e. `lib/clang/{version}/lib/{triple}/libclang_rt.builtins.a`
+//! - with `--libcxx`, libunwind, libc++abi and libc++ are installed into `lib/{triple}` and `include/{triple}`
+//!   of the prefix, where clang searches them for the target
+//!
+//! Added runtimes are recorded in the [manifest](../manifest/index.html) of the build.
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    build::Build,
+    config::*,
+    cross::system_name,
+    entry::{load_entry, CMakeGenerator},
+    error::*,
+    jobs::Parallelism,
+    logs::BuildLog,
+    manifest::{now, Manifest},
+};
+
+/// Runtime added to a build, recorded in its manifest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RuntimeRecord {
+    /// Target triple, e.g. "wasm32-wasi"
+    pub target: String,
+    pub sysroot: Option<PathBuf>,
+    /// Installed components, e.g. "builtins" and "libcxx"
+    pub components: Vec<String>,
+    /// RFC 3339 timestamp when the runtime has been installed
+    pub installed_at: String,
+}
+
+/// Setting of `llvmenv add-runtime`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeSetting {
+    pub target: String,
+    pub sysroot: Option<PathBuf>,
+    /// Also build libunwind, libc++abi and libc++
+    pub libcxx: bool,
+    /// Additional cmake options
+    pub option: BTreeMap<String, String>,
+}
+
+/// Build and install runtimes for the target into the build
+pub fn add_runtime(
+    build: &Build,
+    setting: &RuntimeSetting,
+    jobs: &Parallelism,
+    log: Option<&BuildLog>,
+) -> Result<RuntimeRecord> {
+    if let Some(host) = build.foreign_host() {
+        return Err(Error::ForeignHostBuild {
+            name: build.name().into(),
+            host,
+        });
+    }
+    let mut manifest = Manifest::load(build.prefix())?.unwrap_or_default();
+    let entry = match &manifest.entry {
+        Some(name) => load_entry(name)?,
+        None => {
+            return Err(Error::UnknownEntryOfBuild {
+                name: build.name().into(),
+            })
+        }
+    };
+    let bin = build.prefix().join("bin");
+    let (stdout, _) = Command::new(bin.join("clang"))
+        .arg("-print-resource-dir")
+        .check_output()?;
+    let resource_dir = PathBuf::from(stdout.trim());
+    let work = cache_dir()?
+        .join("runtimes")
+        .join(build.name())
+        .join(&setting.target);
+    let mut components = Vec::new();
+
+    let builtins_src = entry.runtime_source("compiler-rt")?.join("lib/builtins");
+    let builtins = Runtime {
+        name: "builtins",
+        src: builtins_src,
+        build_dir: work.join("builtins"),
+        install_prefix: resource_dir,
+        option: builtins_option(build.prefix()),
+    };
+    builtins.run(build.prefix(), setting, entry.generator(), jobs, log)?;
+    components.push("builtins".to_string());
+
+    if setting.libcxx {
+        let runtimes = Runtime {
+            name: "libcxx",
+            src: entry.runtime_source("runtimes")?,
+            build_dir: work.join("libcxx"),
+            install_prefix: build.prefix().to_owned(),
+            option: libcxx_option(),
+        };
+        runtimes.run(build.prefix(), setting, entry.generator(), jobs, log)?;
+        components.extend(["libunwind", "libcxxabi", "libcxx"].map(String::from));
+    }
+
+    let record = RuntimeRecord {
+        target: setting.target.clone(),
+        sysroot: setting.sysroot.clone(),
+        components,
+        installed_at: now(),
+    };
+    manifest.runtimes.retain(|r| r.target != record.target);
+    manifest.runtimes.push(record.clone());
+    manifest.save(build.prefix())?;
+    Ok(record)
+}
+
+/// One cmake project of runtimes
+struct Runtime {
+    name: &'static str,
+    src: PathBuf,
+    build_dir: PathBuf,
+    install_prefix: PathBuf,
+    option: BTreeMap<String, String>,
+}
+
+impl Runtime {
+    fn run(
+        &self,
+        prefix: &Path,
+        setting: &RuntimeSetting,
+        generator: &CMakeGenerator,
+        jobs: &Parallelism,
+        log: Option<&BuildLog>,
+    ) -> Result<()> {
+        if !self.src.exists() {
+            return Err(Error::RuntimeSourceNotFound {
+                name: self.name.into(),
+                path: self.src.clone(),
+            });
+        }
+        // Configure from scratch since the compiler checks are cached
+        if self.build_dir.exists() {
+            fs::remove_dir_all(&self.build_dir).with(&self.build_dir)?;
+        }
+        fs::create_dir_all(&self.build_dir).with(&self.build_dir)?;
+        let phase = |p: &str| log.map(|log| log.phase(&format!("{}-{}", self.name, p)));
+
+        info!(
+            "Configure {} for {}: {}",
+            self.name,
+            setting.target,
+            self.build_dir.display()
+        );
+        let mut option = target_option(prefix, setting);
+        option.extend(self.option.clone());
+        option.insert(
+            "CMAKE_INSTALL_PREFIX".into(),
+            self.install_prefix.display().to_string(),
+        );
+        option.extend(setting.option.clone());
+        Command::new("cmake")
+            .args(generator.option())
+            .arg(&self.src)
+            .args(option.iter().map(|(k, v)| format!("-D{}={}", k, v)))
+            .current_dir(&self.build_dir)
+            .check_run_to(phase("configure").as_deref())?;
+
+        info!("Build {}", self.name);
+        Command::new("cmake")
+            .arg("--build")
+            .arg(&self.build_dir)
+            .args(generator.build_option(jobs.compile, crate::entry::BuildType::Release))
+            .check_run_to(phase("build").as_deref())?;
+
+        info!(
+            "Install {} into {}",
+            self.name,
+            self.install_prefix.display()
+        );
+        Command::new("cmake")
+            .arg("--build")
+            .arg(&self.build_dir)
+            .args(["--target", "install"])
+            .check_run_to(phase("install").as_deref())?;
+        Ok(())
+    }
+}
+
+/// cmake options to compile for the target by the clang of the build in `prefix`
+fn target_option(prefix: &Path, setting: &RuntimeSetting) -> BTreeMap<String, String> {
+    let bin = prefix.join("bin");
+    let mut option = BTreeMap::new();
+    let mut set = |k: &str, v: String| {
+        option.insert(k.to_string(), v);
+    };
+    for (key, tool) in [
+        ("CMAKE_C_COMPILER", "clang"),
+        ("CMAKE_CXX_COMPILER", "clang++"),
+        ("CMAKE_ASM_COMPILER", "clang"),
+        ("CMAKE_AR", "llvm-ar"),
+        ("CMAKE_NM", "llvm-nm"),
+        ("CMAKE_RANLIB", "llvm-ranlib"),
+    ] {
+        set(key, bin.join(tool).display().to_string());
+    }
+    for lang in ["C", "CXX", "ASM"] {
+        set(
+            &format!("CMAKE_{}_COMPILER_TARGET", lang),
+            setting.target.clone(),
+        );
+    }
+    set("CMAKE_SYSTEM_NAME", system_name(&setting.target).into());
+    set("CMAKE_BUILD_TYPE", "Release".into());
+    // The target may have no libc to link test programs
+    set("CMAKE_TRY_COMPILE_TARGET_TYPE", "STATIC_LIBRARY".into());
+    set("LLVM_ENABLE_PER_TARGET_RUNTIME_DIR", "ON".into());
+    if let Some(sysroot) = &setting.sysroot {
+        set("CMAKE_SYSROOT", sysroot.display().to_string());
+    }
+    option
+}
+
+fn builtins_option(prefix: &Path) -> BTreeMap<String, String> {
+    let mut option = BTreeMap::new();
+    option.insert("COMPILER_RT_DEFAULT_TARGET_ONLY".into(), "ON".into());
+    option.insert("COMPILER_RT_BAREMETAL_BUILD".into(), "ON".into());
+    option.insert(
+        "LLVM_CMAKE_DIR".into(),
+        prefix.join("lib/cmake/llvm").display().to_string(),
+    );
+    option
+}
+
+fn libcxx_option() -> BTreeMap<String, String> {
+    let mut option = BTreeMap::new();
+    option.insert(
+        "LLVM_ENABLE_RUNTIMES".into(),
+        "libunwind;libcxxabi;libcxx".into(),
+    );
+    // Targets without a dynamic loader are common
+    for lib in ["LIBUNWIND", "LIBCXXABI", "LIBCXX"] {
+        option.insert(format!("{}_ENABLE_SHARED", lib), "OFF".into());
+    }
+    option.insert("LIBCXXABI_USE_LLVM_UNWINDER".into(), "ON".into());
+    option
+}
+
+/// Parse `KEY=VALUE` of `-D` option
+///
+/// ```
+/// use llvmenv::runtime::parse_define;
+/// assert_eq!(
+///     parse_define("LIBCXX_ENABLE_THREADS=OFF").unwrap(),
+///     ("LIBCXX_ENABLE_THREADS".to_string(), "OFF".to_string())
+/// );
+/// assert!(parse_define("LIBCXX_ENABLE_THREADS").is_err());
+/// ```
+pub fn parse_define(define: &str) -> std::result::Result<(String, String), String> {
+    match define.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.into(), v.into())),
+        _ => Err(format!("Expected KEY=VALUE: {}", define)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_metal_option() {
+        let setting = RuntimeSetting {
+            target: "arm-none-eabi".into(),
+            sysroot: None,
+            libcxx: false,
+            option: BTreeMap::new(),
+        };
+        let option = target_option(Path::new("/opt/llvm"), &setting);
+        assert_eq!(option["CMAKE_C_COMPILER"], "/opt/llvm/bin/clang");
+        assert_eq!(option["CMAKE_ASM_COMPILER_TARGET"], "arm-none-eabi");
+        assert_eq!(option["CMAKE_SYSTEM_NAME"], "Generic");
+        assert!(!option.contains_key("CMAKE_SYSROOT"));
+    }
+}
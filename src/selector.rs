@@ -0,0 +1,217 @@
+//! Content of `.llvmenv` and `.llvm-version` files
+//!
+//! In addition to a build name, these files can hold a version requirement,
+//! which selects the newest installed build matching it:
+//!
+//! ```text
+//! 17
+//! ```
+//!
+//! ```text
+//! >=16, <18
+//! ```
+//!
+//! or a TOML table with a name and/or a requirement, and tools which the build has to provide:
+//!
+//! ```toml
+//! version = ">=16"
+//! components = ["clang-tidy", "ld.lld"]
+//! ```
+//!
+//! A plain text is regarded as a requirement if it starts with an operator (`>=16`, `^17.0`, `~17.0.1`)
+//! or is a major or major.minor version (`17`, `17.0`), unless a build or alias of the name exists.
+//! Otherwise, e.g. `17.0.6`, it is a build name.
+
+use semver::{Version, VersionReq};
+use serde_derive::Deserialize;
+
+use crate::build::*;
+
+/// Which build is wanted by a `.llvmenv` file
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Selector {
+    /// Name or alias of the build
+    pub name: Option<String>,
+    /// Version requirement, e.g. ">=16, <18"
+    pub version: Option<String>,
+    /// Tools which the build has to provide, e.g. "clang-tidy"
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// Plain text regarded as a requirement, which is preferred as a name if such a build or alias exists
+    #[serde(skip)]
+    plain: Option<String>,
+}
+
+impl Selector {
+    /// Parse the TOML form, or the plain text form
+    ///
+    /// ```
+    /// use llvmenv::selector::Selector;
+    /// assert_eq!(Selector::parse("17.0.6").unwrap().name.as_deref(), Some("17.0.6"));
+    /// assert_eq!(Selector::parse("17").unwrap().version.as_deref(), Some("17"));
+    /// assert_eq!(Selector::parse(">=16, <18").unwrap().version.as_deref(), Some(">=16, <18"));
+    /// let toml = Selector::parse("version = \"17\"\ncomponents = [\"clang-tidy\"]").unwrap();
+    /// assert_eq!(toml.components, ["clang-tidy"]);
+    /// assert!(Selector::parse("version = \"latest\"").is_err());
+    /// ```
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let content = content.trim();
+        let selector = match toml::from_str::<Selector>(content) {
+            Ok(selector) if selector.name.is_some() || selector.version.is_some() => selector,
+            Ok(_) => return Err("Neither name nor version is set".into()),
+            Err(_) if is_requirement(content) => Selector {
+                version: Some(content.into()),
+                plain: Some(content.into()),
+                ..Default::default()
+            },
+            Err(_) => Selector {
+                name: Some(content.into()),
+                ..Default::default()
+            },
+        };
+        selector.requirement()?;
+        Ok(selector)
+    }
+
+    fn requirement(&self) -> Result<Option<VersionReq>, String> {
+        self.version
+            .as_ref()
+            .map(|req| {
+                VersionReq::parse(req)
+                    .map_err(|e| format!("invalid version requirement {}: {}", req, e))
+            })
+            .transpose()
+    }
+
+    /// Whether the build of the version satisfies this selector, or the reason why not
+    fn check(&self, build: &Build, version: Option<&Version>) -> Result<(), Verdict> {
+        if let Some(req) = self.requirement().map_err(Verdict::Invalid)? {
+            match version {
+                Some(version) if req.matches(version) => {}
+                Some(version) => {
+                    return Err(Verdict::NoMatch(format!(
+                        "{} is {}, which does not match {}",
+                        build.name(),
+                        version,
+                        req
+                    )))
+                }
+                None => {
+                    return Err(Verdict::NoMatch(format!(
+                        "version of {} is unknown",
+                        build.name()
+                    )))
+                }
+            }
+        }
+        let missing: Vec<String> = self
+            .components
+            .iter()
+            .filter(|tool| !build.prefix().join("bin").join(tool).is_file())
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Verdict::MissingComponents(missing))
+        }
+    }
+
+    /// The named build, or the newest installed build which satisfies the requirement.
+    /// Builds which cannot run on this machine are never selected.
+    pub fn select(&self) -> Result<Build, Verdict> {
+        // `17` in the plain form is a build or alias of the name if it exists, as before requirements
+        if let Some(name) = &self.plain {
+            if Build::from_name(name).is_ok_and(|build| build.exists()) {
+                return Selector {
+                    name: Some(name.clone()),
+                    ..Default::default()
+                }
+                .select();
+            }
+        }
+        if let Some(name) = &self.name {
+            let build = Build::from_name(name).map_err(|e| Verdict::Invalid(e.to_string()))?;
+            if !build.exists() {
+                return Err(Verdict::Missing(build.name().into()));
+            }
+            if let Some(host) = build.foreign_host() {
+                return Err(Verdict::ForeignHost(build.name().into(), host));
+            }
+            let version = match self.version {
+                Some(_) => build.version().ok(),
+                None => None,
+            };
+            self.check(&build, version.as_ref())?;
+            return Ok(build);
+        }
+        let builds = builds().map_err(|e| Verdict::Invalid(e.to_string()))?;
+        builds
+            .into_iter()
+            .filter(|build| build.exists() && build.foreign_host().is_none())
+            .filter_map(|build| {
+                let version = build.version().ok()?;
+                self.check(&build, Some(&version)).ok()?;
+                Some((version, build))
+            })
+            .max_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, build)| build)
+            .ok_or_else(|| {
+                let mut message = format!(
+                    "no installed build matches {}",
+                    self.version.as_deref().unwrap_or_default()
+                );
+                if !self.components.is_empty() {
+                    message += &format!(" with {}", self.components.join(", "));
+                }
+                Verdict::NoMatch(message)
+            })
+    }
+}
+
+/// Whether the plain text is a version requirement rather than a build name
+fn is_requirement(text: &str) -> bool {
+    if text.starts_with(['>', '<', '=', '^', '~', '*']) {
+        return true;
+    }
+    let parts: Vec<&str> = text.split('.').collect();
+    parts.len() <= 2
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requirement_or_name() {
+        assert!(is_requirement("17"));
+        assert!(is_requirement("17.0"));
+        assert!(is_requirement("~17.0.1"));
+        assert!(!is_requirement("17.0.6"));
+        assert!(!is_requirement("my-trunk"));
+        assert!(!is_requirement("system-14"));
+    }
+
+    #[test]
+    fn build_named_like_requirement() -> crate::error::Result<()> {
+        let _dirs = crate::config::isolate_dirs();
+        let prefix = crate::config::data_dir()?.join("17");
+        std::fs::create_dir_all(&prefix).unwrap();
+        let selected = Selector::parse("17").unwrap().select();
+        std::fs::remove_dir_all(&prefix).unwrap();
+        assert_eq!(selected.unwrap().name(), "17");
+        Ok(())
+    }
+
+    #[test]
+    fn major_matches_minor_releases() {
+        let selector = Selector::parse("17").unwrap();
+        let req = selector.requirement().unwrap().unwrap();
+        assert!(req.matches(&Version::new(17, 0, 6)));
+        assert!(!req.matches(&Version::new(18, 1, 0)));
+    }
+}
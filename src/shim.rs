@@ -0,0 +1,193 @@
+//! Shims to use builds without the shell integration
+//!
+//! `llvmenv rehash` writes a small script into `$XDG_DATA_HOME/llvmenv-shims` for every tool
+//! in the builds, e.g. `clang`, `ld.lld` or `llvm-config`. The shim runs `llvmenv shim-exec {tool}`,
+//! which seeks the build from its working directory as `llvmenv prefix` does, and executes the tool of the build.
+//! Put the directory into `PATH` to use it from bash, IDEs, cron or Makefiles:
+//!
+//! ```shell
+//! export PATH=$HOME/.local/share/llvmenv-shims:$PATH
+//! ```
+//!
+//! The shims are re-generated after `build-entry` and `link` once `rehash` has been run.
+
+use log::info;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{build::*, config::*, error::*, exec::*, plan::shell_quote};
+
+/// Directory of the shims, which is not managed by `rehash` unless it exists
+pub fn shims_dir() -> Result<PathBuf> {
+    Ok(dirs::data_dir()
+        .ok_or(Error::UnsupportedOS)?
+        .join(format!("{}-shims", APP_NAME)))
+}
+
+/// Tools in the builds. Tools of `/usr` are not included since they are in `PATH` anyway.
+pub fn tools(builds: &[Build]) -> BTreeSet<String> {
+    let mut tools = BTreeSet::new();
+    for build in builds.iter().filter(|b| b.prefix() != Path::new("/usr")) {
+        let bin = build.prefix().join("bin");
+        let files = match fs::read_dir(&bin) {
+            Ok(files) => files,
+            Err(_) => continue,
+        };
+        for file in files.flatten() {
+            if file.path().is_file() {
+                tools.insert(file.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    tools
+}
+
+/// Content of the shim of the tool
+///
+/// ```
+/// use llvmenv::shim::shim_script;
+/// let script = shim_script("/usr/local/bin/llvmenv".as_ref(), "clang++");
+/// assert!(script.ends_with("exec /usr/local/bin/llvmenv shim-exec clang++ -- \"$@\"\n"));
+/// let script = shim_script("/opt/my tools/llvmenv".as_ref(), "clang$(id)");
+/// assert!(script.ends_with("exec '/opt/my tools/llvmenv' shim-exec 'clang$(id)' -- \"$@\"\n"));
+/// ```
+pub fn shim_script(llvmenv: &Path, tool: &str) -> String {
+    format!(
+        "#!/bin/sh\n# Generated by llvmenv rehash\nexec {} shim-exec {} -- \"$@\"\n",
+        shell_quote(&llvmenv.to_string_lossy()),
+        shell_quote(tool)
+    )
+}
+
+/// Re-create the shims for all tools of all builds, and returns the number of them
+///
+/// The shims are written into a temporary directory, which replaces the shims directory at last
+/// so that the shims keep working while rehashing.
+pub fn rehash() -> Result<usize> {
+    let dir = shims_dir()?;
+    let parent = dir.parent().expect("shims dir has parent");
+    fs::create_dir_all(parent).with(parent)?;
+    let llvmenv = std::env::current_exe().with(&dir)?;
+    let tools = tools(&builds()?);
+    let new = tempfile::Builder::new()
+        .prefix(".llvmenv-shims-new")
+        .tempdir_in(parent)
+        .with(parent)?;
+    write_shims(new.path(), &llvmenv, &tools)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // Temporary directories are only for the owner
+        fs::set_permissions(new.path(), fs::Permissions::from_mode(0o755)).with(new.path())?;
+    }
+    let old = parent.join(format!(".{}-shims-old", APP_NAME));
+    if old.exists() {
+        fs::remove_dir_all(&old).with(&old)?;
+    }
+    if dir.exists() {
+        fs::rename(&dir, &old).with(&dir)?;
+    }
+    fs::rename(new.keep(), &dir).with(&dir)?;
+    if old.exists() {
+        fs::remove_dir_all(&old).with(&old)?;
+    }
+    info!("{} shims are written into {}", tools.len(), dir.display());
+    Ok(tools.len())
+}
+
+fn write_shims(dir: &Path, llvmenv: &Path, tools: &BTreeSet<String>) -> Result<()> {
+    for tool in tools {
+        let path = dir.join(tool);
+        fs::write(&path, shim_script(llvmenv, tool)).with(&path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).with(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Build and path of the tool which the shim executes in the current directory
+pub fn resolve(tool: &str) -> Result<(Build, PathBuf)> {
+    resolve_in(seek_installed_build()?, tool)
+}
+
+fn resolve_in(build: Build, tool: &str) -> Result<(Build, PathBuf)> {
+    match which(&build, tool) {
+        Some(path) => Ok((build, path)),
+        None => Err(Error::ToolNotFound {
+            tool: tool.into(),
+            build: build.name().into(),
+            setting: match build.set_by() {
+                Some(setting) => format!("set by {}", setting),
+                None => "no .llvmenv is found".into(),
+            },
+            providers: providers(tool)?
+                .iter()
+                .map(|b| b.name().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        }),
+    }
+}
+
+/// Re-create the shims if `rehash` has been used
+pub fn rehash_if_enabled() -> Result<()> {
+    if shims_dir()?.exists() {
+        rehash()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn shim_passes_arguments() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::TempDir::new().unwrap();
+        // Fake llvmenv which prints its arguments, in a directory whose name needs quoting
+        let bin = dir.path().join("my $HOME `bin`");
+        fs::create_dir(&bin).with(&bin)?;
+        let llvmenv = bin.join("llvmenv");
+        fs::write(&llvmenv, "#!/bin/sh\nprintf '%s|' \"$@\"\n").with(&llvmenv)?;
+        fs::set_permissions(&llvmenv, fs::Permissions::from_mode(0o755)).with(&llvmenv)?;
+        let tool = "clang\"$(touch injected)";
+        let tools = [tool.to_string()].into_iter().collect();
+        write_shims(dir.path(), &llvmenv, &tools)?;
+        let (stdout, _) = std::process::Command::new(dir.path().join(tool))
+            .args(["--version", "a b"])
+            .current_dir(dir.path())
+            .check_output()?;
+        assert_eq!(stdout, format!("shim-exec|{}|--|--version|a b|", tool));
+        assert!(!dir.path().join("injected").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_tool_of_build() -> Result<()> {
+        let _dirs = isolate_dirs();
+        let prefix = tempfile::TempDir::new().unwrap();
+        let bin = prefix.path().join("bin");
+        fs::create_dir(&bin).with(&bin)?;
+        fs::write(bin.join("clang"), "").with(&bin)?;
+        let (_, path) = resolve_in(Build::from_path(prefix.path()), "clang")?;
+        assert_eq!(path, bin.join("clang"));
+        match resolve_in(Build::from_path(prefix.path()), "llvmenv-no-such-tool") {
+            Err(Error::ToolNotFound {
+                tool, providers, ..
+            }) => {
+                assert_eq!(tool, "llvmenv-no-such-tool");
+                assert_eq!(providers, "");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        Ok(())
+    }
+}
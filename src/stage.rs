@@ -0,0 +1,355 @@
+//! Multi-stage bootstrap builds
+//!
+//! An entry with `stages = 2` first builds a stage-1 clang with the host compiler,
+//! and then rebuilds LLVM with that clang. The stages are configured in separate build directories
+//! (`build-stage1`, `build-stage2`, ..., and `build` for the final stage),
+//! and only the final stage is installed into the prefix of the entry.
+//!
+//! The `bootstrap` table tunes the intermediate stages:
+//!
+//! ```toml
+//! [self-hosted]
+//! url = "https://github.com/llvm/llvm-project"
+//! project = true
+//! option = { LLVM_ENABLE_PROJECTS = "clang" }
+//!
+//! [self-hosted.bootstrap]
+//! stages = 2
+//! lld = true      # link the next stage by lld of the previous stage
+//! libcxx = false  # build the next stage against libc++ of the previous stage
+//! build_type = "Release"
+//! option = { LLVM_ENABLE_ASSERTIONS = "OFF" }
+//! ```
+//!
+//! Intermediate stages are built only for the native target, without tests, examples and benchmarks.
+//!
+//! Optimized toolchains
+//! --------------------
+//!
+//! `optimize = "pgo"`, `"lto"` or `"pgo+lto"` builds the final stage with profile guided optimization
+//! and/or ThinLTO. They imply a bootstrap build with lld since the host compiler may not support them.
+//! For PGO, an instrumented clang is built by the previous stage, and a training workload is run with it.
+//! By default the workload compiles a part of LLVM itself. It can be replaced by shell commands,
+//! which are run with `CC` and `CXX` set to the instrumented clang:
+//!
+//! ```toml
+//! [fast-clang]
+//! url = "https://github.com/llvm/llvm-project"
+//! project = true
+//! option = { LLVM_ENABLE_PROJECTS = "clang;lld" }
+//! optimize = "pgo+lto"
+//! training = ["$CXX -O2 -c /path/to/my/heavy.cpp -o /dev/null"]
+//! ```
+//!
+//! The raw profiles are merged by `llvm-profdata` of the previous stage,
+//! and passed to the final stage as `LLVM_PROFDATA_FILE`.
+
+use serde_derive::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{cross::Toolchain, entry::BuildType};
+
+/// `optimize` setting of an entry
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Optimize {
+    /// Profile guided optimization
+    #[serde(rename = "pgo")]
+    Pgo,
+    /// ThinLTO
+    #[serde(rename = "lto")]
+    Lto,
+    #[serde(rename = "pgo+lto")]
+    PgoLto,
+}
+
+impl Optimize {
+    pub fn pgo(self) -> bool {
+        matches!(self, Optimize::Pgo | Optimize::PgoLto)
+    }
+
+    pub fn lto(self) -> bool {
+        matches!(self, Optimize::Lto | Optimize::PgoLto)
+    }
+}
+
+impl fmt::Display for Optimize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Optimize::Pgo => "pgo",
+            Optimize::Lto => "lto",
+            Optimize::PgoLto => "pgo+lto",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// `bootstrap` table of an entry
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Bootstrap {
+    /// Number of stages including the final one
+    #[serde(default = "default_stages")]
+    pub stages: u32,
+
+    /// Link the next stage by lld of the previous stage
+    #[serde(default)]
+    pub lld: bool,
+
+    /// Build the next stage against libc++ of the previous stage
+    #[serde(default)]
+    pub libcxx: bool,
+
+    /// `CMAKE_BUILD_TYPE` of intermediate stages
+    #[serde(default)]
+    pub build_type: BuildType,
+
+    /// Additional cmake options of intermediate stages
+    #[serde(default)]
+    pub option: HashMap<String, String>,
+}
+
+fn default_stages() -> u32 {
+    2
+}
+
+impl Bootstrap {
+    pub fn new(stages: u32) -> Self {
+        Bootstrap {
+            stages,
+            lld: false,
+            libcxx: false,
+            build_type: BuildType::default(),
+            option: HashMap::new(),
+        }
+    }
+
+    /// cmake options of an intermediate stage, on top of the options of the entry
+    ///
+    /// `monorepo` is true for entries of the llvm-project layout, where lld and libc++
+    /// have to be enabled by `LLVM_ENABLE_PROJECTS` and `LLVM_ENABLE_RUNTIMES`.
+    pub fn stage_option(
+        &self,
+        monorepo: bool,
+        entry_option: &HashMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        let mut option = BTreeMap::new();
+        for key in [
+            "LLVM_INCLUDE_TESTS",
+            "LLVM_INCLUDE_EXAMPLES",
+            "LLVM_INCLUDE_BENCHMARKS",
+        ] {
+            option.insert(key.to_string(), "OFF".to_string());
+        }
+        option.insert("LLVM_TARGETS_TO_BUILD".into(), "Native".into());
+        if monorepo {
+            let mut projects = vec!["clang"];
+            if self.lld {
+                projects.push("lld");
+            }
+            option.insert(
+                "LLVM_ENABLE_PROJECTS".into(),
+                append_list(entry_option.get("LLVM_ENABLE_PROJECTS"), &projects),
+            );
+            if self.libcxx {
+                option.insert(
+                    "LLVM_ENABLE_RUNTIMES".into(),
+                    append_list(
+                        entry_option.get("LLVM_ENABLE_RUNTIMES"),
+                        &["libcxx", "libcxxabi"],
+                    ),
+                );
+            }
+        }
+        for (k, v) in &self.option {
+            option.insert(k.clone(), v.clone());
+        }
+        option
+    }
+}
+
+/// Append items into a `;`-separated cmake list unless they are already included
+///
+/// ```
+/// use llvmenv::stage::append_list;
+/// assert_eq!(append_list(Some(&"clang;lld".to_string()), &["clang", "lld"]), "clang;lld");
+/// assert_eq!(append_list(Some(&"mlir".to_string()), &["clang"]), "mlir;clang");
+/// assert_eq!(append_list(None, &["clang", "lld"]), "clang;lld");
+/// ```
+pub fn append_list(list: Option<&String>, items: &[&str]) -> String {
+    let mut list: Vec<&str> = list
+        .map(|l| l.split(';').filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    for item in items {
+        if !list.contains(item) {
+            list.push(item);
+        }
+    }
+    list.join(";")
+}
+
+/// cmake options to compile a stage by the clang in `bin`
+pub fn compiler_option(bin: &Path, lld: bool, libcxx: bool) -> BTreeMap<String, String> {
+    let mut option = BTreeMap::new();
+    for (key, tool) in [
+        ("CMAKE_C_COMPILER", "clang"),
+        ("CMAKE_CXX_COMPILER", "clang++"),
+        ("CMAKE_AR", "llvm-ar"),
+        ("CMAKE_RANLIB", "llvm-ranlib"),
+    ] {
+        option.insert(key.into(), bin.join(tool).display().to_string());
+    }
+    if lld {
+        // clang finds ld.lld in its own directory
+        option.insert("LLVM_ENABLE_LLD".into(), "ON".into());
+    }
+    if libcxx {
+        option.insert("LLVM_ENABLE_LIBCXX".into(), "ON".into());
+    }
+    option
+}
+
+/// cmake options to compile by the clang of an installed build in `bin`
+///
+/// Different from [compiler_option], archivers and lld are used only if they are installed.
+pub fn host_compiler_option(bin: &Path) -> BTreeMap<String, String> {
+    let mut option = BTreeMap::new();
+    for (key, tool) in [
+        ("CMAKE_C_COMPILER", "clang"),
+        ("CMAKE_CXX_COMPILER", "clang++"),
+        ("CMAKE_AR", "llvm-ar"),
+        ("CMAKE_RANLIB", "llvm-ranlib"),
+    ] {
+        let path = bin.join(tool);
+        if key.ends_with("COMPILER") || path.exists() {
+            option.insert(key.into(), path.display().to_string());
+        }
+    }
+    if bin.join("ld.lld").exists() {
+        option.insert("LLVM_ENABLE_LLD".into(), "ON".into());
+    }
+    option
+}
+
+/// PGO training run after building an instrumented stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct Training {
+    /// `llvm-profdata` which merges the raw profiles
+    pub profdata: PathBuf,
+    /// Merged profile, passed to the final stage as `LLVM_PROFDATA_FILE`
+    pub profile: PathBuf,
+}
+
+/// One configure-build cycle of an entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    /// Prefix of the log phases, e.g. "stage1". Empty for the final stage.
+    pub name: String,
+    pub build_dir: PathBuf,
+    pub build_type: BuildType,
+    /// cmake options which overwrite the options of the entry
+    pub option: BTreeMap<String, String>,
+    /// Whether this stage is installed into the prefix of the entry
+    pub install: bool,
+    /// Training run after the build if this stage is instrumented
+    pub train: Option<Training>,
+    /// Targets to be built. Empty means the default target.
+    pub targets: Vec<String>,
+    /// Toolchain written into the cmake toolchain file if this stage is cross-compiled
+    pub toolchain: Option<Toolchain>,
+}
+
+impl Stage {
+    /// The only stage of a usual build, or the last stage of a bootstrap build
+    pub fn final_stage(build_dir: PathBuf, build_type: BuildType) -> Self {
+        Stage {
+            name: String::new(),
+            build_dir,
+            build_type,
+            option: BTreeMap::new(),
+            install: true,
+            train: None,
+            targets: Vec::new(),
+            toolchain: None,
+        }
+    }
+
+    /// Log phase of this stage, e.g. "stage1-build"
+    ///
+    /// ```
+    /// use llvmenv::{entry::BuildType, stage::Stage};
+    /// let mut stage = Stage::final_stage("build".into(), BuildType::Release);
+    /// assert_eq!(stage.phase("build"), "build");
+    /// stage.name = "stage1".into();
+    /// assert_eq!(stage.phase("build"), "stage1-build");
+    /// ```
+    pub fn phase(&self, phase: &str) -> String {
+        if self.name.is_empty() {
+            phase.into()
+        } else {
+            format!("{}-{}", self.name, phase)
+        }
+    }
+
+    /// Directory of the tools built in this stage
+    pub fn bin_dir(&self) -> PathBuf {
+        self.build_dir.join("bin")
+    }
+
+    /// Directory where the instrumented tools write raw profiles
+    pub fn profiles_dir(&self) -> PathBuf {
+        self.build_dir.join("profiles")
+    }
+
+    /// Working directory of the training workload
+    pub fn training_dir(&self) -> PathBuf {
+        self.build_dir.join("training")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monorepo_stage_option() {
+        let bootstrap = Bootstrap {
+            lld: true,
+            libcxx: true,
+            ..Bootstrap::new(2)
+        };
+        let mut entry_option = HashMap::new();
+        entry_option.insert("LLVM_ENABLE_PROJECTS".into(), "clang;mlir".into());
+        let option = bootstrap.stage_option(true, &entry_option);
+        assert_eq!(option["LLVM_ENABLE_PROJECTS"], "clang;mlir;lld");
+        assert_eq!(option["LLVM_ENABLE_RUNTIMES"], "libcxx;libcxxabi");
+        assert_eq!(option["LLVM_TARGETS_TO_BUILD"], "Native");
+
+        // Options in the bootstrap table are respected
+        let mut bootstrap = Bootstrap::new(2);
+        bootstrap
+            .option
+            .insert("LLVM_TARGETS_TO_BUILD".into(), "X86;AArch64".into());
+        let option = bootstrap.stage_option(false, &HashMap::new());
+        assert_eq!(option["LLVM_TARGETS_TO_BUILD"], "X86;AArch64");
+        assert!(!option.contains_key("LLVM_ENABLE_PROJECTS"));
+    }
+
+    #[test]
+    fn installed_tools_only() -> std::io::Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        for tool in ["clang", "clang++", "llvm-ar", "ld.lld"] {
+            std::fs::write(dir.path().join(tool), "")?;
+        }
+        let option = host_compiler_option(dir.path());
+        assert_eq!(
+            option["CMAKE_AR"],
+            dir.path().join("llvm-ar").display().to_string()
+        );
+        assert!(!option.contains_key("CMAKE_RANLIB"));
+        assert_eq!(option["LLVM_ENABLE_LLD"], "ON");
+        Ok(())
+    }
+}
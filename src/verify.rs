@@ -0,0 +1,288 @@
+//! Smoke tests of an installed build
+//!
+//! After `llvmenv build-entry` installs a build, and by `llvmenv verify {build}`,
+//! these tests check that the toolchain actually works:
+//!
+//! - `llvm-config --version` matches the version of the entry
+//! - hello-world in C and C++ compiled and linked by the new clang runs
+//! - if lld is installed, hello-world linked with `-fuse-ld=lld` runs
+//! - if libc++ is installed, hello-world with `-stdlib=libc++` runs
+//!
+//! The results are recorded in the [manifest](../manifest/index.html) of the build.
+
+use semver::Version;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tempfile::TempDir;
+
+use crate::{
+    build::Build,
+    error::*,
+    manifest::{now, Manifest},
+};
+
+const HELLO_C: &str = r#"#include <stdio.h>
+int main(void) { printf("Hello, llvmenv\n"); return 0; }
+"#;
+
+const HELLO_CPP: &str = r#"#include <iostream>
+int main() { std::cout << "Hello, llvmenv" << std::endl; return 0; }
+"#;
+
+const HELLO_OUTPUT: &str = "Hello, llvmenv";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    /// The component is not installed
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SmokeTest {
+    pub name: String,
+    pub status: TestStatus,
+    pub message: String,
+}
+
+impl fmt::Display for SmokeTest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mark = match self.status {
+            TestStatus::Passed => " ok ",
+            TestStatus::Failed => "FAIL",
+            TestStatus::Skipped => "skip",
+        };
+        write!(f, "[{}] {}: {}", mark, self.name, self.message)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    /// RFC 3339 timestamp of the run
+    pub checked_at: String,
+    pub tests: Vec<SmokeTest>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.tests.iter().all(|t| t.status != TestStatus::Failed)
+    }
+}
+
+/// Run smoke tests, and record the result into the manifest of the build
+///
+/// The expected version is taken from the manifest. System builds are tested but not recorded.
+/// Foreign-host builds are not tested.
+pub fn verify_build(build: &Build) -> Result<VerifyReport> {
+    let manifest = Manifest::load(build.prefix())?;
+    let foreign_host = manifest.as_ref().and_then(|m| m.foreign_host.clone());
+    let expected = manifest
+        .as_ref()
+        .and_then(|m| m.version.as_ref())
+        .and_then(|v| Version::parse(v).ok());
+    let report = match foreign_host {
+        Some(host) => VerifyReport {
+            checked_at: now(),
+            tests: vec![skipped(
+                "foreign-host",
+                format!("The build runs on {}, and cannot be tested here", host),
+            )],
+        },
+        None => verify(build, expected.as_ref())?,
+    };
+    if build.is_managed() {
+        let mut manifest = manifest.unwrap_or_default();
+        manifest.verify = Some(report.clone());
+        manifest.save(build.prefix())?;
+    }
+    Ok(report)
+}
+
+/// Run smoke tests for the build. `expected` is the version which `llvm-config` should report.
+pub fn verify(build: &Build, expected: Option<&Version>) -> Result<VerifyReport> {
+    let prefix = build.prefix();
+    let bin = prefix.join("bin");
+    let work = TempDir::new().with(env::temp_dir())?;
+    let mut tests = vec![check_llvm_config(build, expected)];
+
+    let clang = bin.join("clang");
+    let clangxx = bin.join("clang++");
+    let c_src = write_source(work.path(), "hello.c", HELLO_C)?;
+    let cpp_src = write_source(work.path(), "hello.cpp", HELLO_CPP)?;
+
+    tests.push(if clang.exists() {
+        hello(&clang, &c_src, &[], &[], work.path(), "hello-c")
+    } else {
+        skipped("hello-c", "clang is not installed")
+    });
+    tests.push(if clangxx.exists() {
+        hello(&clangxx, &cpp_src, &[], &[], work.path(), "hello-cpp")
+    } else {
+        skipped("hello-cpp", "clang++ is not installed")
+    });
+    tests.push(if !clang.exists() {
+        skipped("lld", "clang is not installed")
+    } else if bin.join("ld.lld").exists() {
+        // clang finds ld.lld in its own bin dir
+        hello(
+            &clang,
+            &c_src,
+            &["-fuse-ld=lld".into()],
+            &[],
+            work.path(),
+            "lld",
+        )
+    } else {
+        skipped("lld", "lld is not installed")
+    });
+    let libcxx_dirs = libcxx_lib_dirs(prefix);
+    tests.push(if !clangxx.exists() {
+        skipped("libcxx", "clang++ is not installed")
+    } else if has_libcxx(prefix) {
+        let mut flags = vec!["-stdlib=libc++".to_string()];
+        for dir in &libcxx_dirs {
+            flags.push(format!("-Wl,-rpath,{}", dir.display()));
+        }
+        hello(
+            &clangxx,
+            &cpp_src,
+            &flags,
+            &libcxx_dirs,
+            work.path(),
+            "libcxx",
+        )
+    } else {
+        skipped("libcxx", "libc++ is not installed")
+    });
+
+    Ok(VerifyReport {
+        checked_at: now(),
+        tests,
+    })
+}
+
+fn passed(name: &str, message: impl Into<String>) -> SmokeTest {
+    SmokeTest {
+        name: name.into(),
+        status: TestStatus::Passed,
+        message: message.into(),
+    }
+}
+
+fn failed(name: &str, message: impl Into<String>) -> SmokeTest {
+    SmokeTest {
+        name: name.into(),
+        status: TestStatus::Failed,
+        message: message.into(),
+    }
+}
+
+fn skipped(name: &str, message: impl Into<String>) -> SmokeTest {
+    SmokeTest {
+        name: name.into(),
+        status: TestStatus::Skipped,
+        message: message.into(),
+    }
+}
+
+fn write_source(dir: &Path, name: &str, src: &str) -> Result<PathBuf> {
+    let path = dir.join(name);
+    fs::write(&path, src).with(&path)?;
+    Ok(path)
+}
+
+fn check_llvm_config(build: &Build, expected: Option<&Version>) -> SmokeTest {
+    let name = "llvm-config";
+    match (build.version(), expected) {
+        (Ok(found), Some(expected)) if &found == expected => {
+            passed(name, format!("{} as expected", found))
+        }
+        (Ok(found), Some(expected)) => failed(
+            name,
+            format!("reports {}, but {} is expected", found, expected),
+        ),
+        (Ok(found), None) => passed(name, found.to_string()),
+        (Err(e), _) => failed(name, e.to_string()),
+    }
+}
+
+/// Compile, link and run a hello-world
+fn hello(
+    compiler: &Path,
+    src: &Path,
+    flags: &[String],
+    lib_dirs: &[PathBuf],
+    work: &Path,
+    name: &str,
+) -> SmokeTest {
+    let exe = work.join(name);
+    if let Err(e) = Command::new(compiler)
+        .arg(src)
+        .args(flags)
+        .arg("-o")
+        .arg(&exe)
+        .check_output()
+    {
+        return failed(name, format!("compile failed: {}", command_message(&e)));
+    }
+    let mut run = Command::new(&exe);
+    if !lib_dirs.is_empty() {
+        let mut paths = lib_dirs.to_vec();
+        if let Some(old) = env::var_os("LD_LIBRARY_PATH") {
+            paths.extend(env::split_paths(&old));
+        }
+        run.env("LD_LIBRARY_PATH", env::join_paths(paths).unwrap());
+    }
+    match run.check_output() {
+        Ok((stdout, _)) if stdout.trim() == HELLO_OUTPUT => {
+            let mut cmd = compiler.display().to_string();
+            for flag in flags {
+                cmd.push(' ');
+                cmd.push_str(flag);
+            }
+            passed(name, cmd)
+        }
+        Ok((stdout, _)) => failed(name, format!("unexpected output: {:?}", stdout)),
+        Err(e) => failed(name, format!("run failed: {}", command_message(&e))),
+    }
+}
+
+/// Error message with stderr of the command
+fn command_message(e: &Error) -> String {
+    match e {
+        Error::CommandError {
+            stderr: Some(stderr),
+            ..
+        } if !stderr.trim().is_empty() => stderr.trim().to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// libc++ headers are installed into `include/c++/v1`, or `include/{triple}/c++/v1`
+fn has_libcxx(prefix: &Path) -> bool {
+    prefix.join("include/c++/v1").is_dir()
+        || glob::glob(&format!("{}/include/*/c++/v1", prefix.display()))
+            .map(|mut paths| paths.next().is_some())
+            .unwrap_or(false)
+}
+
+/// Directories which may contain libc++.so: `lib` and `lib/{triple}`
+fn libcxx_lib_dirs(prefix: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![prefix.join("lib")];
+    if let Ok(paths) = glob::glob(&format!("{}/lib/*/libc++.so*", prefix.display())) {
+        for path in paths.flatten() {
+            if let Some(dir) = path.parent() {
+                if !dirs.iter().any(|d| d == dir) {
+                    dirs.push(dir.into());
+                }
+            }
+        }
+    }
+    dirs
+}